@@ -1,6 +1,5 @@
 #[derive(Debug, PartialEq, Clone, Copy)]
 #[allow(dead_code)]
-
 pub enum InstructionType {
     Undefined,
     None,
@@ -1,6 +1,6 @@
 use crate::{
     enums::{address_mode::AddressMode, register_type::RegisterType},
-    modules::{bus::Bus, cpu::Cpu, timer::Timer},
+    modules::cpu::Cpu,
 };
 
 impl Cpu {
@@ -16,17 +16,14 @@ impl Cpu {
             AddressMode::Rr => self.fetched_data = self.read_register(self.instruction.reg2),
 
             AddressMode::Rd8 => {
-                self.fetched_data = Bus::read(self, self.registers.pc) as u16;
-                Timer::cycles(self, 1);
+                self.fetched_data = self.read_cycle(self.registers.pc) as u16;
                 self.registers.pc += 1
             }
 
             AddressMode::Rd16 | AddressMode::D16 => {
-                let lo: u16 = Bus::read(self, self.registers.pc) as u16;
-                Timer::cycles(self, 1);
+                let lo: u16 = self.read_cycle(self.registers.pc) as u16;
 
-                let hi: u16 = Bus::read(self, self.registers.pc + 1) as u16;
-                Timer::cycles(self, 1);
+                let hi: u16 = self.read_cycle(self.registers.pc + 1) as u16;
 
                 self.fetched_data = lo | (hi << 8);
                 self.registers.pc += 2
@@ -49,15 +46,12 @@ impl Cpu {
                     addr |= 0xFF00;
                 }
 
-                self.fetched_data = Bus::read(self, addr) as u16;
-                Timer::cycles(self, 1)
+                self.fetched_data = self.read_cycle(addr) as u16
             }
 
             AddressMode::Rhli => {
                 let addr: u16 = self.read_register(self.instruction.reg2);
-                self.fetched_data = Bus::read(self, addr) as u16;
-
-                Timer::cycles(self, 1);
+                self.fetched_data = self.read_cycle(addr) as u16;
 
                 let data: u16 = self.read_register(RegisterType::Hl).wrapping_add(1);
                 self.set_register(RegisterType::Hl, data)
@@ -65,9 +59,7 @@ impl Cpu {
 
             AddressMode::Rhld => {
                 let addr: u16 = self.read_register(self.instruction.reg2);
-                self.fetched_data = Bus::read(self, addr) as u16;
-
-                Timer::cycles(self, 1);
+                self.fetched_data = self.read_cycle(addr) as u16;
 
                 let data: u16 = self.read_register(RegisterType::Hl).wrapping_sub(1);
                 self.set_register(RegisterType::Hl, data)
@@ -92,37 +84,31 @@ impl Cpu {
             }
 
             AddressMode::Ra8 => {
-                self.fetched_data = Bus::read(self, self.registers.pc) as u16;
-                Timer::cycles(self, 1);
+                self.fetched_data = self.read_cycle(self.registers.pc) as u16;
                 self.registers.pc += 1
             }
 
             AddressMode::A8R => {
-                let bus_data: u16 = Bus::read(self, self.registers.pc) as u16;
+                let bus_data: u16 = self.read_cycle(self.registers.pc) as u16;
                 self.mem_dest = bus_data | 0xFF00;
                 self.dest_is_mem = true;
-                Timer::cycles(self, 1);
                 self.registers.pc += 1
             }
 
             AddressMode::HlSpR => {
-                self.fetched_data = Bus::read(self, self.registers.pc) as u16;
-                Timer::cycles(self, 1);
+                self.fetched_data = self.read_cycle(self.registers.pc) as u16;
                 self.registers.pc += 1
             }
 
             AddressMode::D8 => {
-                self.fetched_data = Bus::read(self, self.registers.pc) as u16;
-                Timer::cycles(self, 1);
+                self.fetched_data = self.read_cycle(self.registers.pc) as u16;
                 self.registers.pc += 1
             }
 
             AddressMode::A16R | AddressMode::D16R => {
-                let lo: u16 = Bus::read(self, self.registers.pc) as u16;
-                Timer::cycles(self, 1);
+                let lo: u16 = self.read_cycle(self.registers.pc) as u16;
 
-                let hi: u16 = Bus::read(self, self.registers.pc + 1) as u16;
-                Timer::cycles(self, 1);
+                let hi: u16 = self.read_cycle(self.registers.pc + 1) as u16;
 
                 self.mem_dest = lo | (hi << 8);
                 self.dest_is_mem = true;
@@ -132,8 +118,7 @@ impl Cpu {
             }
 
             AddressMode::MrD8 => {
-                self.fetched_data = Bus::read(self, self.registers.pc) as u16;
-                Timer::cycles(self, 1);
+                self.fetched_data = self.read_cycle(self.registers.pc) as u16;
 
                 self.registers.pc += 1;
                 self.mem_dest = self.read_register(self.instruction.reg1);
@@ -145,23 +130,18 @@ impl Cpu {
                 self.dest_is_mem = true;
 
                 let addr: u16 = self.read_register(self.instruction.reg1);
-                self.fetched_data = Bus::read(self, addr) as u16;
-
-                Timer::cycles(self, 1)
+                self.fetched_data = self.read_cycle(addr) as u16
             }
 
             AddressMode::Ra16 => {
-                let lo: u16 = Bus::read(self, self.registers.pc) as u16;
-                Timer::cycles(self, 1);
+                let lo: u16 = self.read_cycle(self.registers.pc) as u16;
 
-                let hi: u16 = Bus::read(self, self.registers.pc + 1) as u16;
-                Timer::cycles(self, 1);
+                let hi: u16 = self.read_cycle(self.registers.pc + 1) as u16;
 
                 let addr: u16 = lo | (hi << 8);
 
                 self.registers.pc += 2;
-                self.fetched_data = Bus::read(self, addr) as u16;
-                Timer::cycles(self, 1)
+                self.fetched_data = self.read_cycle(addr) as u16
             }
         }
     }
@@ -2,7 +2,6 @@ use crate::enums::address_mode::AddressMode;
 use crate::enums::condition_type::ConditionType;
 use crate::enums::instruction_type::InstructionType;
 use crate::enums::register_type::RegisterType;
-use crate::modules::bus::Bus;
 use crate::modules::common::set_bit;
 use crate::modules::cpu::Cpu;
 use crate::modules::stack::Stack;
@@ -20,18 +19,21 @@ impl Cpu {
     fn process_ld(&mut self) {
         if self.dest_is_mem {
             if Self::is_16bit(self.instruction.reg2) {
-                Timer::cycles(self, 1);
-                Bus::write16(self, self.mem_dest, self.fetched_data);
+                self.write_cycle(self.mem_dest, self.fetched_data as u8);
+                self.write_cycle(
+                    self.mem_dest.wrapping_add(1),
+                    (self.fetched_data >> 8) as u8,
+                );
             } else {
-                Bus::write(self, self.mem_dest, self.fetched_data as u8);
+                self.write_cycle(self.mem_dest, self.fetched_data as u8);
             }
 
-            Timer::cycles(self, 1);
-
             return;
         }
 
         if self.instruction.addr_mode == AddressMode::HlSpR {
+            Timer::cycles(self, 1);
+
             let hflag: bool = (self.read_register(self.instruction.reg2) & 0xF)
                 + (self.fetched_data & 0xF)
                 >= 0x10;
@@ -50,20 +52,23 @@ impl Cpu {
             return;
         }
 
+        if self.instruction.reg1 == RegisterType::Sp
+            && self.instruction.addr_mode == AddressMode::Rr
+        {
+            // LD SP,HL
+            Timer::cycles(self, 1);
+        }
+
         self.set_register(self.instruction.reg1, self.fetched_data);
     }
 
     fn process_ldh(&mut self) {
         if self.instruction.reg1 == RegisterType::A {
-            self.set_register(
-                self.instruction.reg1,
-                Bus::read(self, 0xFF00 | self.fetched_data) as u16,
-            );
+            let value = self.read_cycle(0xFF00 | self.fetched_data);
+            self.set_register(self.instruction.reg1, value as u16);
         } else {
-            self::Bus::write(self, self.mem_dest, self.registers.a);
+            self.write_cycle(self.mem_dest, self.registers.a);
         }
-
-        Timer::cycles(self, 1);
     }
 
     fn process_jp(&mut self) {
@@ -91,9 +96,7 @@ impl Cpu {
 
         if self.check_condition() {
             let lo: u16 = Stack::pop(self) as u16;
-            Timer::cycles(self, 1);
             let hi: u16 = Stack::pop(self) as u16;
-            Timer::cycles(self, 1);
 
             let n: u16 = (hi << 8) | lo;
             self.registers.pc = n;
@@ -109,9 +112,7 @@ impl Cpu {
 
     fn process_pop(&mut self) {
         let lo: u16 = Stack::pop(self) as u16;
-        Timer::cycles(self, 1);
         let hi: u16 = Stack::pop(self) as u16;
-        Timer::cycles(self, 1);
 
         let n: u16 = (hi << 8) | lo;
 
@@ -123,31 +124,29 @@ impl Cpu {
     }
 
     fn process_push(&mut self) {
-        let hi = (self.read_register(self.instruction.reg1) >> 8) & 0xFF;
         Timer::cycles(self, 1);
+
+        let hi = (self.read_register(self.instruction.reg1) >> 8) & 0xFF;
         Stack::push(self, hi as u8);
 
         let lo = self.read_register(self.instruction.reg1) & 0xFF;
-        Timer::cycles(self, 1);
         Stack::push(self, lo as u8);
-
-        Timer::cycles(self, 1);
     }
 
     fn process_inc(&mut self) {
         let mut val: u16 = self.read_register(self.instruction.reg1).wrapping_add(1);
 
-        if Self::is_16bit(self.instruction.reg1) {
-            Timer::cycles(self, 1);
-        }
-
         if self.instruction.reg1 == RegisterType::Hl
             && self.instruction.addr_mode == AddressMode::Mr
         {
-            val = (Bus::read(self, self.read_register(RegisterType::Hl)) as u16).wrapping_add(1);
-            val &= 0xFF;
-            Bus::write(self, self.read_register(RegisterType::Hl), val as u8);
+            // The read of (HL) already took its cycle in fetch_data.
+            val = self.fetched_data.wrapping_add(1) & 0xFF;
+            self.write_cycle(self.mem_dest, val as u8);
         } else {
+            if Self::is_16bit(self.instruction.reg1) {
+                Timer::cycles(self, 1);
+            }
+
             self.set_register(self.instruction.reg1, val);
             val = self.read_register(self.instruction.reg1);
         }
@@ -162,16 +161,16 @@ impl Cpu {
     fn process_dec(&mut self) {
         let mut val: u16 = self.read_register(self.instruction.reg1).wrapping_sub(1);
 
-        if Self::is_16bit(self.instruction.reg1) {
-            Timer::cycles(self, 1);
-        }
-
         if self.instruction.reg1 == RegisterType::Hl
             && self.instruction.addr_mode == AddressMode::Mr
         {
-            val = (Bus::read(self, self.read_register(RegisterType::Hl)) as u16).wrapping_sub(1);
-            Bus::write(self, self.read_register(RegisterType::Hl), val as u8);
+            val = self.fetched_data.wrapping_sub(1) & 0xFF;
+            self.write_cycle(self.mem_dest, val as u8);
         } else {
+            if Self::is_16bit(self.instruction.reg1) {
+                Timer::cycles(self, 1);
+            }
+
             self.set_register(self.instruction.reg1, val);
             val = self.read_register(self.instruction.reg1);
         }
@@ -192,6 +191,8 @@ impl Cpu {
         }
 
         if self.instruction.reg1 == RegisterType::Sp {
+            // ADD SP,e8 spends two internal cycles after reading the operand.
+            Timer::cycles(self, 1);
             val = self
                 .read_register(self.instruction.reg1)
                 .wrapping_add_signed(self.fetched_data as i8 as i16) as u32;
@@ -342,12 +343,6 @@ impl Cpu {
         let bit_op: u8 = (op >> 6) & 0b11;
         let mut reg_val: u8 = self.read_register_8bits(reg);
 
-        Timer::cycles(self, 1);
-
-        if reg == RegisterType::Hl {
-            Timer::cycles(self, 2);
-        }
-
         match bit_op {
             1 => {
                 // BIT
@@ -417,7 +412,7 @@ impl Cpu {
                     Some(reg_val == 0),
                     Some(false),
                     Some(false),
-                    Some(old & 0x80 == 0),
+                    Some(old & 0x80 != 0),
                 );
                 return;
             }
@@ -524,12 +519,20 @@ impl Cpu {
         self.set_flags(Some(false), Some(false), Some(false), Some(c != 0));
     }
 
+    // DMG STOP: DIV is reset and the CPU sleeps until a button in a selected
+    // row is pressed. The byte after the opcode is skipped.
     fn process_stop(&mut self) {
-        panic!("STOP FUNCTION NOT IMPLEMENTED");
+        Timer::write(self, 0xFF04, 0);
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+        self.stopped = true;
     }
 
     fn process_halt(&mut self) {
-        self.halted = true;
+        if !self.int_master_enabled && self.interrupt_flags & self.ie_register & 0x1F != 0 {
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
     }
 
     fn process_daa(&mut self) {
@@ -625,13 +628,17 @@ impl Cpu {
 
     pub fn goto_addr(&mut self, addr: u16, pushpc: bool) {
         if self.check_condition() {
+            // JP HL loads PC straight from the register pair, every other jump
+            // spends one internal cycle before the (optional) push.
+            if self.instruction.addr_mode != AddressMode::R {
+                Timer::cycles(self, 1);
+            }
+
             if pushpc {
-                Timer::cycles(self, 2);
                 Stack::push16(self, self.registers.pc);
             }
 
             self.registers.pc = addr;
-            Timer::cycles(self, 1);
         }
     }
 
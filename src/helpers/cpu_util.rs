@@ -1,8 +1,30 @@
 use crate::enums::register_type::RegisterType;
 use crate::modules::bus::Bus;
 use crate::modules::cpu::Cpu;
+use crate::modules::debugger::Access;
+use crate::modules::timer::Timer;
 
 impl Cpu {
+    // Every bus access by the CPU takes exactly one M-cycle. The access happens
+    // first, then the rest of the machine is ticked for that cycle, so peripherals
+    // always observe CPU reads/writes on the cycle they belong to.
+    pub fn read_cycle(&mut self, address: u16) -> u8 {
+        let value = Bus::read(self, address);
+        if self.debugger.enabled {
+            self.debugger.access(address, value, Access::Read);
+        }
+        Timer::cycles(self, 1);
+        value
+    }
+
+    pub fn write_cycle(&mut self, address: u16, value: u8) {
+        Bus::write(self, address, value);
+        if self.debugger.enabled {
+            self.debugger.access(address, value, Access::Write);
+        }
+        Timer::cycles(self, 1);
+    }
+
     pub fn read_register(&self, reg_type: RegisterType) -> u16 {
         match reg_type {
             RegisterType::A => self.registers.a as u16,
@@ -25,7 +47,7 @@ impl Cpu {
         }
     }
 
-    pub fn read_register_8bits(&self, reg_type: RegisterType) -> u8 {
+    pub fn read_register_8bits(&mut self, reg_type: RegisterType) -> u8 {
         match reg_type {
             RegisterType::A => self.registers.a,
             RegisterType::F => self.registers.f,
@@ -35,7 +57,7 @@ impl Cpu {
             RegisterType::E => self.registers.e,
             RegisterType::H => self.registers.h,
             RegisterType::L => self.registers.l,
-            RegisterType::Hl => Bus::read(self, self.read_register(RegisterType::Hl)),
+            RegisterType::Hl => self.read_cycle(self.read_register(RegisterType::Hl)),
             _other => panic!("INVALID REGISTER TYPE: {:?}", reg_type),
         }
     }
@@ -83,7 +105,7 @@ impl Cpu {
             RegisterType::E => self.registers.e = data,
             RegisterType::H => self.registers.h = data,
             RegisterType::L => self.registers.l = data,
-            RegisterType::Hl => Bus::write(self, self.read_register(RegisterType::Hl), data),
+            RegisterType::Hl => self.write_cycle(self.read_register(RegisterType::Hl), data),
             _other => panic!("INVALID REGISTER TYPE: {:?}", reg_type),
         }
     }
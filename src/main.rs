@@ -7,17 +7,34 @@ mod modules {
     pub mod common;
     pub mod cpu;
     pub mod dbg;
+    pub mod debug_window;
+    pub mod debugger;
+    pub mod disassembler;
     pub mod dma;
     pub mod emu;
     pub mod instruction;
     pub mod interrupts;
     pub mod io;
+    pub mod io_registers;
+    pub mod joypad;
     mod lcd;
+    pub mod link_cable;
+    pub mod movie;
+    pub mod options;
+    pub mod pacer;
     pub mod ppu;
     pub mod ram;
     pub mod registers;
+    pub mod rewind;
+    pub mod savestate;
+    pub mod scanline;
+    pub mod screenshot;
+    pub mod serial;
     pub mod stack;
+    pub mod test_runner;
     pub mod timer;
+    pub mod trace;
+    pub mod video;
 }
 
 mod enums {
@@ -36,18 +53,80 @@ mod helpers {
 
 pub mod constants;
 
-use modules::emu::Emu;
+use modules::{disassembler::Disassembler, emu::Emu, options::Options, test_runner::TestRunner};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 {
-        panic!("No ROM path provided!")
+    let options = Options::parse(&args);
+
+    if options.disassemble {
+        let listing =
+            Disassembler::rom_bank(&options.rom_path, options.bank, options.symbols.as_deref());
+
+        match listing {
+            Ok(listing) => print!("{}", listing),
+            Err(err) => {
+                eprintln!("Can't disassemble: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if options.test {
+        let passed = TestRunner::run_all(&options);
+        std::process::exit(if passed { 0 } else { 1 });
     }
 
-    let rom_path = &args[1];
+    println!("ROM PATH: {}", options.rom_path);
+
+    if let Some(path) = &options.verify {
+        let matches = Emu::verify(&options, path);
+        println!(
+            "{}",
+            if matches {
+                "Movie OK"
+            } else {
+                "Movie desynced"
+            }
+        );
+        std::process::exit(if matches { 0 } else { 1 });
+    }
 
-    println!("ROM PATH: {}", rom_path);
+    if let Some(frames) = options.bench {
+        Emu::bench(&options, frames);
+        return;
+    }
+
+    if let Some(reference) = &options.compare {
+        match Emu::compare(&options, reference) {
+            Ok((0, _)) => println!("Screen matches {}", reference),
+            Ok((differences, diff_path)) => {
+                println!(
+                    "{} pixels differ from {}, see {}",
+                    differences, reference, diff_path
+                );
+                std::process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("Can't compare: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(frames) = options.screenshot {
+        match Emu::capture(&options, frames) {
+            Ok(path) => println!("Saved {}", path),
+            Err(err) => {
+                eprintln!("Can't take a screenshot: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    Emu::run(rom_path.clone());
+    Emu::run(options);
 }
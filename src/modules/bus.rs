@@ -1,6 +1,7 @@
 use crate::modules::cart::Cart;
 use crate::modules::cpu::Cpu;
 use crate::modules::io::IO;
+use crate::modules::joypad::Joypad;
 use crate::modules::ram::Ram;
 
 use super::lcd::Lcd;
@@ -9,6 +10,7 @@ pub struct Bus {
     pub cart: Cart,
     pub ram: Ram,
     pub io: IO,
+    pub joypad: Joypad,
     pub ppu: Ppu,
     pub lcd: Lcd,
 }
@@ -19,25 +21,24 @@ impl Bus {
             cart: Cart::new(),
             ram: Ram::new(),
             io: IO::new(),
+            joypad: Joypad::new(),
             ppu: Ppu::new(),
             lcd: Lcd::new(),
         }
     }
 
     pub fn read(cpu: &Cpu, address: u16) -> u8 {
+        if cpu.dma.blocks(address) {
+            return cpu.dma.conflict_read(address);
+        }
+
         match address {
             0x0000..=0x7FFF => Cart::read(&cpu.bus.cart, address), // ROM
             0x8000..=0x9FFF => Ppu::vram_read(&cpu.bus.ppu, address), // CHAR DATA
             0xA000..=0xBFFF => Cart::read(&cpu.bus.cart, address), // CART RAM
             0xC000..=0xDFFF => Ram::wram_read(&cpu.bus.ram, address), // WRAM
             0xE000..=0xFDFF => 0,                                  // Reserverd ECHO RAM,
-            0xFE00..=0xFE9F => {
-                if cpu.dma.is_trasferring() {
-                    return 0xFF;
-                }
-
-                Ppu::oam_read(&cpu.bus.ppu, address)
-            }
+            0xFE00..=0xFE9F => Ppu::oam_read(&cpu.bus.ppu, address),
             0xFEA0..=0xFEFF => 0, // Reserved
             0xFF00..=0xFF7F => IO::read(cpu, address),
             0xFFFF => cpu.get_ie_register(), // CPU ENABLE REGISTER
@@ -46,6 +47,10 @@ impl Bus {
     }
 
     pub fn write(cpu: &mut Cpu, address: u16, value: u8) {
+        if cpu.dma.blocks(address) {
+            return;
+        }
+
         match address {
             0x0000..=0x7FFF => Cart::write(&mut cpu.bus.cart, address, value), // ROM
             0x8000..=0x9FFF => {
@@ -54,13 +59,7 @@ impl Bus {
             0xA000..=0xBFFF => Cart::write(&mut cpu.bus.cart, address, value), // CART RAM
             0xC000..=0xDFFF => Ram::wram_write(&mut cpu.bus.ram, address, value), // WRAM
             0xE000..=0xFDFF => (), // Reserverd ECHO RAM,
-            0xFE00..=0xFE9F => {
-                if cpu.dma.is_trasferring() {
-                    return;
-                }
-
-                Ppu::oam_write(&mut cpu.bus.ppu, address, value);
-            } // OAM
+            0xFE00..=0xFE9F => Ppu::oam_write(&mut cpu.bus.ppu, address, value), // OAM
             0xFEA0..=0xFEFF => (), // Reserved
             0xFF00..=0xFF7F => {
                 IO::write(cpu, address, value);
@@ -2,10 +2,13 @@ use std::{fs::File, io::Read};
 
 use crate::constants::cartridge_helpers;
 
+use super::savestate::{StateReader, StateWriter};
+
 pub struct Cart {
     entry: [u8; 4],
     logo: [u8; 0x30],
     title: [char; 16],
+    cgb_flag: u8,
     new_lic_code: u16,
     sgb_flag: u8,
     cart_type: u8,
@@ -17,6 +20,15 @@ pub struct Cart {
     checksum: u8,
     global_checksum: u16,
     data: Vec<u8>,
+    // Worked out once the ROM is loaded, save states and movies check it
+    rom_checksum: u32,
+
+    // MBC1 state
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u8,
+    bank_hi: u8,
+    banking_mode: u8,
 }
 
 impl Cart {
@@ -25,6 +37,7 @@ impl Cart {
             entry: [0; 4],
             logo: [0; 0x30],
             title: [' '; 16],
+            cgb_flag: 0,
             new_lic_code: 0,
             sgb_flag: 0,
             cart_type: 0,
@@ -36,15 +49,138 @@ impl Cart {
             checksum: 0,
             global_checksum: 0,
             data: Vec::new(),
+            rom_checksum: 0,
+
+            ram: Vec::new(),
+            ram_enabled: false,
+            rom_bank: 1,
+            bank_hi: 0,
+            banking_mode: 0,
         }
     }
 
     pub fn read(&self, address: u16) -> u8 {
-        self.data[address as usize]
+        match address {
+            0x0000..=0x3FFF => self.rom_byte(self.rom_bank(address), address),
+            0x4000..=0x7FFF => self.rom_byte(self.rom_bank(address), address - 0x4000),
+            0xA000..=0xBFFF => match self.ram_offset(address) {
+                Some(offset) => self.ram[offset],
+                None => 0xFF,
+            },
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        if !self.is_mbc1() {
+            // ROM ONLY carts ignore writes, but may still carry plain RAM
+            if (0xA000..=0xBFFF).contains(&address) {
+                if let Some(offset) = self.ram_offset(address) {
+                    self.ram[offset] = data;
+                }
+            }
+            return;
+        }
+
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = (data & 0x0F) == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = data & 0b1_1111;
+
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.bank_hi = data & 0b11,
+            0x6000..=0x7FFF => self.banking_mode = data & 1,
+            0xA000..=0xBFFF => {
+                if let Some(offset) = self.ram_offset(address) {
+                    self.ram[offset] = data;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // ROM bank mapped at a 0x0000-0x7FFF address
+    pub fn rom_bank(&self, address: u16) -> usize {
+        let bank = if address >= 0x4000 {
+            ((self.bank_hi as usize) << 5) | self.rom_bank as usize
+        } else if self.banking_mode == 1 {
+            (self.bank_hi as usize) << 5
+        } else {
+            0
+        };
+
+        bank % (self.data.len() / 0x4000).max(1)
+    }
+
+    // RAM bank mapped at 0xA000-0xBFFF, None while no RAM is accessible
+    pub fn ram_bank(&self) -> Option<usize> {
+        self.ram_offset(0xA000).map(|offset| offset / 0x2000)
+    }
+
+    // CGB enhanced or CGB only, the last byte of the title on older carts
+    pub fn cgb(&self) -> bool {
+        self.cgb_flag & 0x80 != 0
+    }
+
+    fn is_mbc1(&self) -> bool {
+        (0x01..=0x03).contains(&self.cart_type)
+    }
+
+    fn rom_byte(&self, bank: usize, offset: u16) -> u8 {
+        let banks = (self.data.len() / 0x4000).max(1);
+        let address = (bank % banks) * 0x4000 + offset as usize;
+
+        self.data.get(address).copied().unwrap_or(0xFF)
+    }
+
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram.is_empty() || (self.is_mbc1() && !self.ram_enabled) {
+            return None;
+        }
+
+        let bank = if self.banking_mode == 1 {
+            self.bank_hi as usize
+        } else {
+            0
+        };
+        let offset = bank * 0x2000 + (address as usize - 0xA000);
+
+        Some(offset % self.ram.len())
     }
 
-    pub fn write(&mut self, _address: u16, _data: u8) {
-        // println!("UNSUPPORTED CART WRITE {:04X} Data: {:02X}", address, data)
+    // Identifies which game a save state belongs to
+    pub fn rom_checksum(&self) -> u32 {
+        self.rom_checksum
+    }
+
+    // FNV-1a over the whole ROM
+    fn hash_rom(&self) -> u32 {
+        self.data.iter().fold(0x811C_9DC5, |hash, &byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        })
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.ram_enabled);
+        state.bytes(&[self.rom_bank, self.bank_hi, self.banking_mode]);
+        state.u32(self.ram.len() as u32);
+        state.bytes(&self.ram);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.ram_enabled = state.bool()?;
+        self.rom_bank = state.u8()?;
+        self.bank_hi = state.u8()?;
+        self.banking_mode = state.u8()?;
+
+        if state.u32()? as usize != self.ram.len() {
+            return Err(String::from("cartridge RAM size does not match"));
+        }
+
+        state.bytes(&mut self.ram)
     }
 
     pub fn load(&mut self, filename: &str) -> bool {
@@ -52,6 +188,7 @@ impl Cart {
 
         if let Ok(mut file) = File::open(filename) {
             if file.read_to_end(&mut self.data).is_ok() {
+                self.rom_checksum = self.hash_rom();
                 self.parse_header();
                 return true;
             }
@@ -66,6 +203,7 @@ impl Cart {
             .iter_mut()
             .zip(self.data[0x134..0x144].iter().map(|&c| c as char))
             .for_each(|(t, c)| *t = c);
+        self.cgb_flag = self.data[0x143];
         let raw_new_lic_code = &self.data[0x144..0x146];
         self.new_lic_code = (raw_new_lic_code[0] as u16) | ((raw_new_lic_code[1] as u16) << 8);
         self.sgb_flag = self.data[0x146];
@@ -80,6 +218,16 @@ impl Cart {
         self.global_checksum =
             (raw_global_checksum[0] as u16) | ((raw_global_checksum[1] as u16) << 8);
 
+        let ram_bytes: usize = match self.ram_size {
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0,
+        };
+        self.ram = vec![0; ram_bytes];
+
         println!("Title: {}", self.title.iter().collect::<String>());
         println!(
             "ROM Type: {}",
@@ -104,3 +252,80 @@ impl Cart {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Cart;
+
+    // 2 MB MBC1+RAM+BATTERY cart with 32 KB of RAM, the first byte of every
+    // ROM bank holds its number
+    fn mbc1() -> Cart {
+        let mut cart = Cart::new();
+        cart.cart_type = 0x03;
+        cart.data = vec![0; 128 * 0x4000];
+        for bank in 0..128 {
+            cart.data[bank * 0x4000] = bank as u8;
+        }
+        cart.ram = vec![0; 0x8000];
+        cart
+    }
+
+    #[test]
+    fn switches_rom_bank_at_4000() {
+        let mut cart = mbc1();
+        assert_eq!(cart.read(0x4000), 1);
+
+        cart.write(0x2000, 5);
+        assert_eq!(cart.read(0x4000), 5);
+        assert_eq!(cart.read(0x0000), 0);
+
+        // Bank 0 can't be selected, it maps bank 1
+        cart.write(0x2000, 0);
+        assert_eq!(cart.read(0x4000), 1);
+
+        // Only the low 5 bits count, the upper 2 come from 4000-5FFF
+        cart.write(0x2000, 0x23);
+        cart.write(0x4000, 2);
+        assert_eq!(cart.read(0x4000), 0x43);
+
+        // 0x20, 0x40 and 0x60 map the bank after them
+        cart.write(0x2000, 0x00);
+        assert_eq!(cart.read(0x4000), 0x41);
+    }
+
+    #[test]
+    fn mode_1_banks_0000_and_ram() {
+        let mut cart = mbc1();
+        cart.write(0x0000, 0x0A);
+        cart.write(0x4000, 1);
+
+        assert_eq!(cart.read(0x0000), 0);
+        assert_eq!(cart.ram_bank(), Some(0));
+
+        cart.write(0x6000, 1);
+        assert_eq!(cart.read(0x0000), 0x20);
+        assert_eq!(cart.ram_bank(), Some(1));
+
+        cart.write(0xA000, 0x42);
+        cart.write(0x4000, 0);
+        assert_eq!(cart.read(0xA000), 0);
+
+        cart.write(0x4000, 1);
+        assert_eq!(cart.read(0xA000), 0x42);
+    }
+
+    #[test]
+    fn ram_reads_ff_until_enabled() {
+        let mut cart = mbc1();
+        cart.write(0xA000, 0x42);
+        assert_eq!(cart.read(0xA000), 0xFF);
+        assert_eq!(cart.ram_bank(), None);
+
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA000, 0x42);
+        assert_eq!(cart.read(0xA000), 0x42);
+
+        cart.write(0x0000, 0x00);
+        assert_eq!(cart.read(0xA000), 0xFF);
+    }
+}
@@ -1,5 +1,6 @@
+use std::path::Path;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub fn bit(value: u8, bit: u8) -> bool {
     (value & (1 << bit)) != 0
@@ -16,3 +17,51 @@ pub fn set_bit(value: u8, bit: u8, on: bool) -> u8 {
 pub fn delay(_: u32) {
     thread::sleep(Duration::from_millis(10));
 }
+
+// roms/game.gb -> roms/game-20240131-235959.<extension>, in UTC, with a
+// counter added for more than one file in the same second
+pub fn timestamped_path(rom_path: &str, extension: &str) -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let (year, month, day) = civil_date(seconds / 86400);
+    let time = seconds % 86400;
+
+    let path = Path::new(rom_path);
+    let name = format!(
+        "{}-{:04}{:02}{:02}-{:02}{:02}{:02}",
+        path.file_stem().unwrap_or_default().to_string_lossy(),
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    );
+
+    let mut file = path.with_file_name(format!("{}.{}", name, extension));
+    let mut count = 1;
+    while file.exists() {
+        count += 1;
+        file = path.with_file_name(format!("{}-{}.{}", name, count, extension));
+    }
+
+    file.to_string_lossy().into_owned()
+}
+
+// Year, month and day of a day count since 1970-01-01
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    // Counted in 400 year eras starting in March, so the leap day is last
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+
+    (year, month, day)
+}
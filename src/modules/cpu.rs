@@ -1,6 +1,4 @@
-use std::fs::OpenOptions;
-use std::io::Write;
-
+use crate::enums::instruction_type::InstructionType;
 use crate::modules::bus::Bus;
 use crate::modules::dbg::Dbg;
 use crate::modules::instruction::Instruction;
@@ -8,7 +6,10 @@ use crate::modules::interrupts::interrupt;
 use crate::modules::registers::Registers;
 use crate::modules::timer::Timer;
 
+use super::debugger::Debugger;
 use super::dma::Dma;
+use super::savestate::{StateReader, StateWriter};
+use super::trace::Tracer;
 
 const DEBUG: bool = false;
 
@@ -21,6 +22,11 @@ pub struct Cpu {
     pub instruction: Instruction,
 
     pub halted: bool,
+    // STOP mode, only a joypad input wakes the CPU
+    pub stopped: bool,
+    // HALT with IME off and an interrupt already pending doesn't halt, the
+    // next opcode fetch fails to increment PC instead
+    pub halt_bug: bool,
     pub stepping: bool,
 
     pub int_master_enabled: bool,
@@ -30,6 +36,8 @@ pub struct Cpu {
 
     pub bus: Bus,
     pub dbg: Dbg,
+    pub debugger: Debugger,
+    pub tracer: Option<Tracer>,
     pub timer: Timer,
     pub dma: Dma,
 }
@@ -44,6 +52,8 @@ impl Cpu {
             opcode: 0,
             instruction: Instruction::default(),
             halted: false,
+            stopped: false,
+            halt_bug: false,
             stepping: false,
 
             int_master_enabled: false,
@@ -53,6 +63,8 @@ impl Cpu {
 
             bus: Bus::new(),
             dbg: Dbg::default(),
+            debugger: Debugger::new(),
+            tracer: None,
             timer: Timer::default(),
             dma: Dma::new(),
         }
@@ -78,21 +90,45 @@ impl Cpu {
     }
 
     fn fetch_instruction(&mut self) {
-        self.opcode = Bus::read(self, self.registers.pc);
-        self.registers.pc += 1;
+        self.opcode = self.read_cycle(self.registers.pc);
+
+        if !std::mem::take(&mut self.halt_bug) {
+            self.registers.pc += 1;
+        }
         self.instruction = Instruction::instruction_by_opcode(self.opcode);
     }
 
+    // False once the CPU hit an illegal opcode and can't go on
     pub fn step(&mut self) -> bool {
+        if self.stopped {
+            // The whole machine is frozen, the cycles only keep the frontend's
+            // frame loop going
+            self.timer.ticks += 4;
+
+            if self.bus.joypad.read() & 0x0F != 0x0F {
+                self.stopped = false;
+            }
+            return true;
+        }
+
         if !self.halted {
             let pc: u16 = self.registers.pc;
 
+            if self.tracer.is_some() {
+                Tracer::instruction(self);
+            }
+
             self.fetch_instruction();
-            Timer::cycles(self, 1);
+
+            // Illegal opcodes lock up the CPU, PC is left on the opcode
+            if self.instruction.ins_type == InstructionType::Undefined {
+                self.registers.pc = pc;
+                return false;
+            }
+
             self.fetch_data();
 
             if DEBUG {
-                // self.log();
                 let mut flags: [char; 4] = [' '; 4];
                 let f: &u8 = &self.registers.f;
                 flags[0] = if f & (1 << 7) != 0 { 'Z' } else { '-' };
@@ -114,7 +150,8 @@ impl Cpu {
             // CPU IS HALTED
             Timer::cycles(self, 1);
 
-            if self.interrupt_flags != 0 {
+            // Any enabled interrupt wakes the CPU, whether IME is set or not
+            if self.interrupt_flags & self.ie_register & 0x1F != 0 {
                 self.halted = false;
             }
         }
@@ -138,22 +175,34 @@ impl Cpu {
         self.ie_register = value;
     }
 
-    fn log(&self) {
-        println!("A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
-            self.registers.a, self.registers.f, self.registers.b, self.registers.c, self.registers.d, self.registers.e, self.registers.h, self.registers.l,
-            self.registers.sp, self.registers.pc, Bus::read(self, self.registers.pc), Bus::read(self, self.registers.pc + 1), Bus::read(self, self.registers.pc + 2), Bus::read(self, self.registers.pc + 3)
-        );
-
-        // Write the above print content to file log.txt
-        let mut file = OpenOptions::new()
-            .write(true)
-            .append(true)
-            .open("logs.txt")
-            .unwrap();
-
-        file.write_all(format!("A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}\n",
-            self.registers.a, self.registers.f, self.registers.b, self.registers.c, self.registers.d, self.registers.e, self.registers.h, self.registers.l,
-            self.registers.sp, self.registers.pc, Bus::read(self, self.registers.pc), Bus::read(self, self.registers.pc + 1), Bus::read(self, self.registers.pc + 2), Bus::read(self, self.registers.pc + 3)
-        ).as_bytes()).unwrap();
+    // Registers and interrupt state, the rest of the machine is saved by
+    // its own components. Decoded instruction fields only live within a
+    // step and are not part of it.
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.registers.save_state(state);
+
+        state.bool(self.halted);
+        state.bool(self.stopped);
+        state.bool(self.halt_bug);
+        state.bool(self.stepping);
+        state.bool(self.int_master_enabled);
+        state.bool(self.enabling_ime);
+        state.u8(self.ie_register);
+        state.u8(self.interrupt_flags);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.registers.load_state(state)?;
+
+        self.halted = state.bool()?;
+        self.stopped = state.bool()?;
+        self.halt_bug = state.bool()?;
+        self.stepping = state.bool()?;
+        self.int_master_enabled = state.bool()?;
+        self.enabling_ime = state.bool()?;
+        self.ie_register = state.u8()?;
+        self.interrupt_flags = state.u8()?;
+
+        Ok(())
     }
 }
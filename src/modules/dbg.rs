@@ -1,36 +1,30 @@
-use crate::modules::bus::Bus;
 use crate::modules::cpu::Cpu;
 
+// Collects what test ROMs print over the serial port
+#[derive(Default)]
 pub struct Dbg {
-    msg: [char; 1024],
-    msg_size: usize,
+    msg: String,
 }
 
-impl Default for Dbg {
-    fn default() -> Self {
-        Self {
-            msg: [' '; 1024],
-            msg_size: 0,
+impl Dbg {
+    // True if a byte was added to the message
+    pub fn update(cpu: &mut Cpu) -> bool {
+        match cpu.bus.io.serial.sent.take() {
+            Some(byte) => {
+                cpu.dbg.msg.push(byte as char);
+                true
+            }
+            None => false,
         }
     }
-}
-
-impl Dbg {
-    pub fn update(cpu: &mut Cpu) {
-        if Bus::read(cpu, 0xFF02) == 0x81 {
-            let c = Bus::read(cpu, 0xFF01) as char;
-
-            cpu.dbg.msg[cpu.dbg.msg_size] = c;
-            cpu.dbg.msg_size += 1;
 
-            Bus::write(cpu, 0xFF02, 0);
-        }
+    pub fn message(&self) -> &str {
+        &self.msg
     }
 
     pub fn print(cpu: &Cpu) {
-        if cpu.dbg.msg[0] != ' ' {
-            let dbg_str: String = cpu.dbg.msg[..cpu.dbg.msg_size].iter().collect();
-            println!("DBG {}", dbg_str);
+        if !cpu.dbg.msg.is_empty() {
+            println!("DBG {}", cpu.dbg.msg);
         }
     }
 }
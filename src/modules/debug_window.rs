@@ -0,0 +1,768 @@
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, TextureCreator};
+use sdl2::ttf::Font;
+use sdl2::video::{Window, WindowContext};
+
+use super::{bus::Bus, cpu::Cpu, io_registers::IoRegisters};
+
+const TILE_COLORS: [Color; 4] = [
+    Color::RGB(255, 255, 255),
+    Color::RGB(175, 175, 175),
+    Color::RGB(85, 85, 85),
+    Color::RGB(0, 0, 0),
+];
+
+pub const WIDTH: u32 = 584;
+pub const HEIGHT: u32 = 864;
+
+const TAB_HEIGHT: i32 = 32;
+const BACKGROUND: Color = Color::RGB(17, 17, 17);
+const TAB_COLOR: Color = Color::RGB(40, 40, 40);
+const SELECTED_TAB_COLOR: Color = Color::RGB(80, 80, 80);
+const TEXT_COLOR: Color = Color::RGB(230, 230, 230);
+const DIM_TEXT_COLOR: Color = Color::RGB(120, 120, 120);
+// Behind transparent sprite pixels
+const TRANSPARENT_COLOR: Color = Color::RGB(68, 68, 100);
+// Map overlays: the part of the BG on screen and the window's extent
+const VIEWPORT_COLOR: Color = Color::RGB(255, 64, 64);
+const WINDOW_COLOR: Color = Color::RGB(64, 160, 255);
+const CURSOR_COLOR: Color = Color::RGB(60, 90, 160);
+// IO registers that changed since the previous frame
+const CHANGED_COLOR: Color = Color::RGB(255, 210, 80);
+
+// Rows of 16 bytes shown in the memory tab
+const MEMORY_ROWS: u32 = 38;
+const ROW_HEIGHT: i32 = 18;
+
+type TextFn<'a> = dyn Fn(&mut Canvas<Window>, &str, i32, i32, Color) + 'a;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Tab {
+    Tiles,
+    Map9800,
+    Map9C00,
+    Oam,
+    Memory,
+    Io,
+}
+
+const TABS: [Tab; 6] = [
+    Tab::Tiles,
+    Tab::Map9800,
+    Tab::Map9C00,
+    Tab::Oam,
+    Tab::Memory,
+    Tab::Io,
+];
+
+impl Tab {
+    fn title(self) -> &'static str {
+        match self {
+            Tab::Tiles => "Tiles",
+            Tab::Map9800 => "Map $9800",
+            Tab::Map9C00 => "Map $9C00",
+            Tab::Oam => "OAM",
+            Tab::Memory => "Memory",
+            Tab::Io => "IO",
+        }
+    }
+}
+
+// RGB pixels that end up in a texture, scaled up when copied to the window
+struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; (width * height * 3) as usize],
+        }
+    }
+
+    fn set(&mut self, x: u32, y: u32, color: Color) {
+        let index = ((y * self.width + x) * 3) as usize;
+        self.pixels[index..index + 3].copy_from_slice(&[color.r, color.g, color.b]);
+    }
+
+    fn draw(
+        &self,
+        canvas: &mut Canvas<Window>,
+        texture_creator: &TextureCreator<WindowContext>,
+        x: i32,
+        y: i32,
+        scale: u32,
+    ) {
+        let mut texture = texture_creator
+            .create_texture_static(PixelFormatEnum::RGB24, self.width, self.height)
+            .unwrap();
+        texture
+            .update(None, &self.pixels, (self.width * 3) as usize)
+            .unwrap();
+
+        let dest = Rect::new(x, y, self.width * scale, self.height * scale);
+        canvas.copy(&texture, None, dest).unwrap();
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Prompt {
+    Goto,
+    Search,
+}
+
+// Viewer for VRAM, OAM and memory, one tab at a time
+pub struct DebugWindow {
+    pub tab: Tab,
+    // Set when the window has to be drawn again even if the game didn't
+    // produce a new frame
+    pub redraw: bool,
+
+    // Memory tab: first address shown and the selected byte
+    memory_top: u16,
+    cursor: u16,
+    // First hex digit typed over the selected byte
+    nibble: Option<u8>,
+    prompt: Option<(Prompt, String)>,
+    // Last searched pattern, for F3
+    pattern: Vec<u8>,
+    // Result of the last goto, search or edit
+    status: String,
+
+    // IO register values of the previous and the current frame
+    io_previous: Vec<u8>,
+    io_current: Vec<u8>,
+    io_frame: u32,
+}
+
+impl DebugWindow {
+    pub fn new() -> Self {
+        Self {
+            tab: Tab::Tiles,
+            redraw: true,
+            memory_top: 0xC000,
+            cursor: 0xC000,
+            nibble: None,
+            prompt: None,
+            pattern: Vec::new(),
+            status: String::new(),
+            io_previous: Vec::new(),
+            io_current: Vec::new(),
+            io_frame: 0,
+        }
+    }
+
+    pub fn next_tab(&mut self, step: isize) {
+        let index = TABS.iter().position(|&tab| tab == self.tab).unwrap() as isize;
+        self.tab = TABS[(index + step).rem_euclid(TABS.len() as isize) as usize];
+        self.redraw = true;
+    }
+
+    // Mouse click in the window, the tab bar selects a tab
+    pub fn click(&mut self, x: i32, y: i32) {
+        if (0..TAB_HEIGHT).contains(&y) && x >= 0 {
+            let index = x as usize * TABS.len() / WIDTH as usize;
+
+            if let Some(&tab) = TABS.get(index) {
+                self.tab = tab;
+                self.redraw = true;
+            }
+        }
+    }
+
+    // Key pressed while the window has focus, false if the emulator should
+    // handle it instead
+    pub fn key(&mut self, cpu: &mut Cpu, keycode: Keycode, keymod: Mod) -> bool {
+        if self.tab != Tab::Memory {
+            return false;
+        }
+
+        let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+        self.redraw = true;
+
+        if let Some((prompt, input)) = &mut self.prompt {
+            match keycode {
+                Keycode::Return | Keycode::KpEnter => {
+                    let (prompt, input) = (*prompt, input.clone());
+                    self.prompt = None;
+                    self.run_prompt(cpu, prompt, &input);
+                }
+                Keycode::Backspace => {
+                    input.pop();
+                }
+                Keycode::Escape => self.prompt = None,
+                _ => {}
+            }
+            return true;
+        }
+
+        let cursor = self.cursor;
+        let page = (MEMORY_ROWS * 16) as u16;
+
+        match keycode {
+            Keycode::G if ctrl => self.prompt = Some((Prompt::Goto, String::new())),
+            Keycode::F if ctrl => self.prompt = Some((Prompt::Search, String::new())),
+            Keycode::F3 => self.search(cpu),
+            Keycode::Escape if self.nibble.is_some() => self.nibble = None,
+            Keycode::Left => self.move_cursor(cursor.wrapping_sub(1)),
+            Keycode::Right => self.move_cursor(cursor.wrapping_add(1)),
+            Keycode::Up => self.move_cursor(cursor.wrapping_sub(16)),
+            Keycode::Down => self.move_cursor(cursor.wrapping_add(16)),
+            Keycode::PageUp => self.move_cursor(cursor.saturating_sub(page)),
+            Keycode::PageDown => self.move_cursor(cursor.saturating_add(page)),
+            Keycode::Home => self.move_cursor(0x0000),
+            Keycode::End => self.move_cursor(0xFFFF),
+            // Other keys still reach the emulator (function keys, Escape, Tab)
+            // but letters and digits are typed into the editor
+            Keycode::Escape | Keycode::Tab => return false,
+            _ if Self::is_function_key(keycode) => return false,
+            _ => {}
+        }
+
+        true
+    }
+
+    // Text typed while the window has focus, hex digits edit the selected
+    // byte or go to the prompt
+    pub fn text_input(&mut self, cpu: &mut Cpu, text: &str) {
+        if self.tab != Tab::Memory {
+            return;
+        }
+        self.redraw = true;
+
+        if let Some((_, input)) = &mut self.prompt {
+            input.extend(text.chars().filter(|c| c.is_ascii_hexdigit() || *c == ' '));
+            return;
+        }
+
+        for digit in text.chars().filter_map(|c| c.to_digit(16)) {
+            let digit = digit as u8;
+
+            match self.nibble.take() {
+                None => self.nibble = Some(digit),
+                Some(high) => {
+                    let address = self.cursor;
+
+                    if Self::writable(address) {
+                        Bus::write(cpu, address, high << 4 | digit);
+                        self.status =
+                            format!("Wrote ${:02X} to ${:04X}", high << 4 | digit, address);
+                    } else {
+                        self.status = format!("{} is read-only", Self::region(address));
+                    }
+
+                    self.move_cursor(address.wrapping_add(1));
+                }
+            }
+        }
+    }
+
+    fn is_function_key(keycode: Keycode) -> bool {
+        (Keycode::F1 as i32..=Keycode::F12 as i32).contains(&(keycode as i32))
+    }
+
+    fn move_cursor(&mut self, address: u16) {
+        self.cursor = address;
+        self.nibble = None;
+
+        let row = (address & 0xFFF0) as u32;
+        let top = self.memory_top as u32;
+        let span = MEMORY_ROWS * 16;
+
+        if row < top {
+            self.memory_top = row as u16;
+        } else if row >= top + span {
+            self.memory_top = (row + 16 - span) as u16;
+        }
+    }
+
+    fn run_prompt(&mut self, cpu: &Cpu, prompt: Prompt, input: &str) {
+        let digits: String = input.chars().filter(|c| !c.is_whitespace()).collect();
+
+        match prompt {
+            Prompt::Goto => match u16::from_str_radix(&digits, 16) {
+                Ok(address) => {
+                    self.memory_top = (address & 0xFFF0).min((0x10000 - MEMORY_ROWS * 16) as u16);
+                    self.move_cursor(address);
+                    self.status = String::new();
+                }
+                Err(_) => self.status = format!("Invalid address '{}'", input),
+            },
+            Prompt::Search => {
+                let pattern: Option<Vec<u8>> = (0..digits.len())
+                    .step_by(2)
+                    .map(|i| {
+                        digits
+                            .get(i..i + 2)
+                            .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                    })
+                    .collect();
+
+                match pattern {
+                    Some(pattern) if !pattern.is_empty() => {
+                        self.pattern = pattern;
+                        self.search(cpu);
+                    }
+                    _ => self.status = format!("Invalid pattern '{}', expected hex bytes", input),
+                }
+            }
+        }
+    }
+
+    // Next match of the pattern after the cursor, wrapping around the bus
+    fn search(&mut self, cpu: &Cpu) {
+        if self.pattern.is_empty() {
+            self.status = String::from("Nothing to search for, Ctrl+F enters a pattern");
+            return;
+        }
+
+        let found = (1..=0x10000u32)
+            .map(|offset| self.cursor.wrapping_add(offset as u16))
+            .find(|&start| {
+                self.pattern
+                    .iter()
+                    .enumerate()
+                    .all(|(i, &byte)| Bus::read(cpu, start.wrapping_add(i as u16)) == byte)
+            });
+
+        match found {
+            Some(address) => {
+                self.move_cursor(address);
+                self.status = format!("Found at ${:04X}", address);
+            }
+            None => self.status = String::from("Not found"),
+        }
+    }
+
+    // Name of the part of the address space an address is in
+    fn region(address: u16) -> &'static str {
+        match address {
+            0x0000..=0x3FFF => "ROM0",
+            0x4000..=0x7FFF => "ROMX",
+            0x8000..=0x9FFF => "VRAM",
+            0xA000..=0xBFFF => "SRAM",
+            0xC000..=0xDFFF => "WRAM",
+            0xE000..=0xFDFF => "ECHO",
+            0xFE00..=0xFE9F => "OAM",
+            0xFEA0..=0xFEFF => "----",
+            0xFF00..=0xFF7F => "IO",
+            0xFF80..=0xFFFE => "HRAM",
+            0xFFFF => "IE",
+        }
+    }
+
+    // Writes to ROM select banks instead of changing bytes, the echo and
+    // unusable areas ignore them
+    fn writable(address: u16) -> bool {
+        matches!(
+            address,
+            0x8000..=0xDFFF | 0xFE00..=0xFE9F | 0xFF00..=0xFFFF
+        )
+    }
+
+    pub fn draw(
+        &mut self,
+        cpu: &Cpu,
+        canvas: &mut Canvas<Window>,
+        texture_creator: &TextureCreator<WindowContext>,
+        font: Option<&Font>,
+    ) {
+        canvas.set_draw_color(BACKGROUND);
+        canvas.clear();
+
+        // Change highlights compare against the last frame drawn
+        if cpu.bus.ppu.current_frame != self.io_frame || self.io_current.is_empty() {
+            let values = IoRegisters::read(cpu).iter().map(|reg| reg.value).collect();
+            self.io_previous = std::mem::replace(&mut self.io_current, values);
+            self.io_frame = cpu.bus.ppu.current_frame;
+        }
+
+        let text = |canvas: &mut Canvas<Window>, text: &str, x: i32, y: i32, color: Color| {
+            if let Some(font) = font {
+                Self::text(canvas, texture_creator, font, text, x, y, color);
+            }
+        };
+
+        let tab_width = WIDTH / TABS.len() as u32;
+        for (index, &tab) in TABS.iter().enumerate() {
+            let x = index as i32 * tab_width as i32;
+
+            canvas.set_draw_color(if tab == self.tab {
+                SELECTED_TAB_COLOR
+            } else {
+                TAB_COLOR
+            });
+            canvas
+                .fill_rect(Rect::new(x + 1, 0, tab_width - 2, TAB_HEIGHT as u32))
+                .unwrap();
+            text(canvas, tab.title(), x + 10, 6, TEXT_COLOR);
+        }
+
+        let top = TAB_HEIGHT + 12;
+
+        match self.tab {
+            Tab::Tiles => {
+                let lcd = &cpu.bus.lcd;
+
+                // Tile n of each grid is what a map entry of n refers to in
+                // that addressing mode
+                for (index, &base) in [0x8000, 0x8800].iter().enumerate() {
+                    let x = 24 + index as i32 * 280;
+                    let active = lcd.bgw_data_area() == base;
+                    let mut image = Image::new(128, 128);
+
+                    for tile in 0..256 {
+                        let address = Self::tile_address(base, tile as u8);
+                        Self::draw_tile(cpu, &mut image, address, (tile % 16) * 8, (tile / 16) * 8);
+                    }
+
+                    let label = format!("${:04X} mode{}", base, if active { " (BG)" } else { "" });
+                    text(canvas, &label, x, top, TEXT_COLOR);
+                    image.draw(canvas, texture_creator, x, top + 24, 2);
+                }
+
+                text(canvas, "Drawn with BGP", 24, top + 296, DIM_TEXT_COLOR);
+
+                let palettes = [("BGP", lcd.bgp), ("OBP0", lcd.objp0), ("OBP1", lcd.objp1)];
+                for (row, (name, palette)) in palettes.iter().enumerate() {
+                    let y = top + 336 + row as i32 * 32;
+                    text(
+                        canvas,
+                        &format!("{:<4} ${:02X}", name, palette),
+                        24,
+                        y,
+                        TEXT_COLOR,
+                    );
+
+                    for id in 0..4 {
+                        let shade = (palette >> (id * 2)) & 3;
+                        canvas.set_draw_color(TILE_COLORS[shade as usize]);
+                        canvas
+                            .fill_rect(Rect::new(140 + id * 32, y, 24, 24))
+                            .unwrap();
+                    }
+                }
+            }
+            Tab::Map9800 | Tab::Map9C00 => {
+                let map = if self.tab == Tab::Map9800 {
+                    0x9800
+                } else {
+                    0x9C00
+                };
+                let lcd = &cpu.bus.lcd;
+                let mut image = Image::new(256, 256);
+
+                for entry in 0..1024u32 {
+                    let tile = cpu.bus.ppu.vram[(map - 0x8000) as usize + entry as usize];
+                    let address = Self::tile_address(lcd.bgw_data_area(), tile);
+                    Self::draw_tile(cpu, &mut image, address, (entry % 32) * 8, (entry / 32) * 8);
+                }
+
+                let is_bg = lcd.bg_map_area() == map;
+                let is_window = lcd.window_map_area() == map;
+
+                if is_bg {
+                    Self::outline(
+                        &mut image,
+                        lcd.scx as u32,
+                        lcd.scy as u32,
+                        160,
+                        144,
+                        VIEWPORT_COLOR,
+                    );
+                }
+
+                // The window shows the top left of its map, starting at
+                // WX - 7, WY on screen
+                if is_window && lcd.window_enabled() != 0 && lcd.wx <= 166 && lcd.wy <= 143 {
+                    let width = (167 - lcd.wx as u32).min(160);
+                    let height = 144 - lcd.wy as u32;
+                    Self::outline(&mut image, 0, 0, width, height, WINDOW_COLOR);
+                }
+
+                image.draw(canvas, texture_creator, 36, top, 2);
+
+                let y = top + 524;
+                let used_by = match (is_bg, is_window) {
+                    (true, true) => "BG and window",
+                    (true, false) => "BG",
+                    (false, true) => "window",
+                    (false, false) => "nothing",
+                };
+                text(canvas, &format!("Used by: {}", used_by), 36, y, TEXT_COLOR);
+                text(
+                    canvas,
+                    &format!(
+                        "SCX ${:02X}  SCY ${:02X}  WX ${:02X}  WY ${:02X}  tiles ${:04X}",
+                        lcd.scx,
+                        lcd.scy,
+                        lcd.wx,
+                        lcd.wy,
+                        lcd.bgw_data_area()
+                    ),
+                    36,
+                    y + 26,
+                    TEXT_COLOR,
+                );
+                text(canvas, "Screen", 36, y + 52, VIEWPORT_COLOR);
+                text(canvas, "Window", 120, y + 52, WINDOW_COLOR);
+            }
+            Tab::Oam => {
+                let lcd = &cpu.bus.lcd;
+                let height = lcd.obj_height() as u32;
+
+                for sprite in 0..40 {
+                    let x = (sprite / 20) * 292;
+                    let y = top + 24 + (sprite % 20) * 38;
+                    let entry = &cpu.bus.ppu.oam_ram[sprite as usize * 4..sprite as usize * 4 + 4];
+                    let (sprite_y, sprite_x, tile, flags) =
+                        (entry[0], entry[1], entry[2], entry[3]);
+
+                    let mut image = Image::new(8, height);
+                    Self::draw_sprite(cpu, &mut image, tile, flags);
+                    image.draw(canvas, texture_creator, x + 8, y, 2);
+
+                    let flag = |bit: u8, name: char| if flags & bit != 0 { name } else { '-' };
+                    let line = format!(
+                        "{:02} Y{:3} X{:3} ${:02X} {}{}{} {}",
+                        sprite,
+                        sprite_y,
+                        sprite_x,
+                        tile,
+                        flag(0x80, 'P'),
+                        flag(0x40, 'Y'),
+                        flag(0x20, 'X'),
+                        (flags >> 4) & 1
+                    );
+
+                    // Sprites off the screen are greyed out
+                    let visible = (1..160).contains(&sprite_y) && (1..168).contains(&sprite_x);
+                    let color = if visible { TEXT_COLOR } else { DIM_TEXT_COLOR };
+                    text(canvas, &line, x + 36, y + 4, color);
+                }
+
+                text(
+                    canvas,
+                    "#  Y    X    tile flags (priority, flips) OBP",
+                    8,
+                    top,
+                    DIM_TEXT_COLOR,
+                );
+            }
+            Tab::Memory => {
+                // The cursor is placed by character, the font is monospaced
+                let char_width = font
+                    .and_then(|font| font.size_of("0").ok())
+                    .map_or(8, |(width, _)| width as i32);
+                self.draw_memory(cpu, canvas, &text, char_width);
+            }
+            Tab::Io => {
+                let mut y = top;
+
+                for (index, register) in IoRegisters::read(cpu).iter().enumerate() {
+                    let changed = self
+                        .io_previous
+                        .get(index)
+                        .is_some_and(|&previous| previous != self.io_current[index]);
+                    let color = if changed { CHANGED_COLOR } else { TEXT_COLOR };
+
+                    for (line, fields) in register.fields.iter().enumerate() {
+                        let line = if line == 0 {
+                            format!(
+                                "{:04X} {:<4} {:02X}  {}",
+                                register.address, register.name, register.value, fields
+                            )
+                        } else {
+                            format!("{:16}{}", "", fields)
+                        };
+
+                        text(canvas, &line, 8, y, color);
+                        y += ROW_HEIGHT;
+                    }
+                }
+            }
+        }
+    }
+
+    fn draw_memory(&self, cpu: &Cpu, canvas: &mut Canvas<Window>, text: &TextFn, char_width: i32) {
+        let top = TAB_HEIGHT + 12;
+        let cart = &cpu.bus.cart;
+
+        let ram_bank = match cart.ram_bank() {
+            Some(bank) => format!("{}", bank),
+            None => String::from("off"),
+        };
+        let header = format!(
+            "ROM0 bank {:02X}  ROMX bank {:02X}  SRAM bank {}",
+            cart.rom_bank(0x0000),
+            cart.rom_bank(0x4000),
+            ram_bank
+        );
+        text(canvas, &header, 8, top, TEXT_COLOR);
+
+        // Region, address, then the bytes starting at column 11
+        for row in 0..MEMORY_ROWS {
+            let address = self.memory_top.wrapping_add(row as u16 * 16);
+            let y = top + 28 + row as i32 * ROW_HEIGHT;
+
+            if (self.cursor & 0xFFF0) == address {
+                let column = 11 + (self.cursor & 0xF) as i32 * 3;
+                canvas.set_draw_color(CURSOR_COLOR);
+                canvas
+                    .fill_rect(Rect::new(
+                        8 + column * char_width,
+                        y,
+                        2 * char_width as u32,
+                        ROW_HEIGHT as u32,
+                    ))
+                    .unwrap();
+            }
+
+            let bytes: Vec<String> = (0..16)
+                .map(|i| {
+                    let byte = address.wrapping_add(i);
+                    match self.nibble {
+                        Some(high) if byte == self.cursor => format!("{:X}_", high),
+                        _ => format!("{:02X}", Bus::read(cpu, byte)),
+                    }
+                })
+                .collect();
+
+            let line = format!(
+                "{:<4} {:04X}  {}",
+                Self::region(address),
+                address,
+                bytes.join(" ")
+            );
+            let color = if Self::writable(address) {
+                TEXT_COLOR
+            } else {
+                DIM_TEXT_COLOR
+            };
+            text(canvas, &line, 8, y, color);
+        }
+
+        let y = top + 40 + MEMORY_ROWS as i32 * ROW_HEIGHT;
+        match &self.prompt {
+            Some((prompt, input)) => {
+                let label = if *prompt == Prompt::Goto {
+                    "Go to"
+                } else {
+                    "Search"
+                };
+                text(canvas, &format!("{}: {}_", label, input), 8, y, TEXT_COLOR);
+            }
+            None => text(
+                canvas,
+                "Hex digits edit, Ctrl+G go to, Ctrl+F search, F3 next",
+                8,
+                y,
+                DIM_TEXT_COLOR,
+            ),
+        }
+        text(canvas, &self.status, 8, y + 22, TEXT_COLOR);
+    }
+
+    // Data address of a tile number in the $8000 (unsigned) or $8800
+    // (signed, from $9000) addressing mode
+    fn tile_address(base: u16, tile: u8) -> u16 {
+        if base == 0x8000 {
+            0x8000 + tile as u16 * 16
+        } else {
+            0x9000u16.wrapping_add((tile as i8 as i16 * 16) as u16)
+        }
+    }
+
+    // Color ids of one row of a tile, the first byte holds the low bits
+    fn tile_row(cpu: &Cpu, address: u16, row: u16) -> [u8; 8] {
+        let index = (address - 0x8000 + row * 2) as usize;
+        let lo = cpu.bus.ppu.vram[index];
+        let hi = cpu.bus.ppu.vram[index + 1];
+        let mut ids = [0; 8];
+
+        for (x, id) in ids.iter_mut().enumerate() {
+            let bit = 7 - x;
+            *id = ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1);
+        }
+
+        ids
+    }
+
+    fn draw_tile(cpu: &Cpu, image: &mut Image, address: u16, x: u32, y: u32) {
+        for row in 0..8 {
+            for (col, &id) in Self::tile_row(cpu, address, row).iter().enumerate() {
+                image.set(x + col as u32, y + row as u32, cpu.bus.lcd.bg_color(id));
+            }
+        }
+    }
+
+    // A sprite as the PPU would draw it, with its palette and flips
+    fn draw_sprite(cpu: &Cpu, image: &mut Image, tile: u8, flags: u8) {
+        let height = image.height;
+        // Bit 0 of the tile number is ignored for 8x16 sprites
+        let tile = if height == 16 { tile & 0xFE } else { tile };
+        let palette = (flags >> 4) & 1;
+
+        for y in 0..height {
+            let row = if flags & 0x40 != 0 { height - 1 - y } else { y };
+            let ids = Self::tile_row(cpu, 0x8000 + tile as u16 * 16, row as u16);
+
+            for x in 0..8 {
+                let id = ids[if flags & 0x20 != 0 { 7 - x } else { x }];
+                let color = if id == 0 {
+                    TRANSPARENT_COLOR
+                } else {
+                    cpu.bus.lcd.obj_color(palette, id)
+                };
+                image.set(x as u32, y, color);
+            }
+        }
+    }
+
+    // Rectangle outline on the 256x256 map, wrapping around its edges
+    fn outline(image: &mut Image, x: u32, y: u32, width: u32, height: u32, color: Color) {
+        for i in 0..width {
+            image.set((x + i) % 256, y % 256, color);
+            image.set((x + i) % 256, (y + height - 1) % 256, color);
+        }
+
+        for i in 0..height {
+            image.set(x % 256, (y + i) % 256, color);
+            image.set((x + width - 1) % 256, (y + i) % 256, color);
+        }
+    }
+
+    fn text(
+        canvas: &mut Canvas<Window>,
+        texture_creator: &TextureCreator<WindowContext>,
+        font: &Font,
+        text: &str,
+        x: i32,
+        y: i32,
+        color: Color,
+    ) {
+        // SDL_ttf can't render an empty string
+        if text.is_empty() {
+            return;
+        }
+
+        let surface = font.render(text).blended(color).unwrap();
+        let texture = texture_creator
+            .create_texture_from_surface(&surface)
+            .unwrap();
+
+        canvas
+            .copy(
+                &texture,
+                None,
+                Rect::new(x, y, surface.width(), surface.height()),
+            )
+            .unwrap();
+    }
+}
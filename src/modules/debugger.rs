@@ -0,0 +1,596 @@
+use std::io::{self, BufRead, Write};
+
+use crate::enums::{instruction_type::InstructionType, register_type::RegisterType};
+
+use super::{bus::Bus, cpu::Cpu, disassembler::Disassembler, instruction::Instruction};
+
+const HELP: &str = "\
+Addresses and values are hex ($ and 0x prefixes are optional), counts are decimal.
+  s, step [n]                 execute n instructions (default 1)
+  n, next                     step over calls and rsts
+  f, finish                   run until the current function returns
+  c, continue                 run until a breakpoint or watchpoint
+  b, break <addr> [if <reg> <op> <value>]
+                              break at addr, op is one of == != < > <= >=
+  w, watch <addr>[-<end>] [r|w|rw]
+                              break on CPU reads/writes of an address range
+  i, info                     list breakpoints and watchpoints
+  delete [n], unwatch [n]     remove a breakpoint/watchpoint, or all of them
+  r, regs                     show registers
+  x <addr> [len]              dump memory (default 64 bytes)
+  set <reg|addr> <value>      change a register or write to memory
+  d, dis [addr] [n]           disassemble n instructions (around PC by default)
+  q, quit                     quit the emulator
+An empty line repeats the last command. The window doesn't update while
+the debugger is waiting for input.";
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+struct Condition {
+    register: RegisterType,
+    compare: Compare,
+    value: u16,
+}
+
+struct Breakpoint {
+    address: u16,
+    condition: Option<Condition>,
+}
+
+struct Watchpoint {
+    start: u16,
+    end: u16,
+    access: Access,
+}
+
+struct WatchHit {
+    index: usize,
+    address: u16,
+    value: u8,
+    access: Access,
+}
+
+// REPL debugger on stdin. The frontend asks should_break before every
+// instruction while it is enabled and runs repl when it says so.
+pub struct Debugger {
+    pub enabled: bool,
+    break_requested: bool,
+
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Option<WatchHit>,
+    // Instruction that made the access a watchpoint caught
+    instruction_pc: u16,
+
+    // Instructions left before breaking again
+    steps: u32,
+    // Return address of the call being stepped over
+    step_over: Option<u16>,
+    // SP when finish was entered, and whether the last instruction was a ret
+    finish_sp: Option<u16>,
+    returning: bool,
+    // Set when the REPL returns, the instruction it stopped at was already
+    // checked
+    resuming: bool,
+
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            break_requested: false,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            instruction_pc: 0,
+            steps: 0,
+            step_over: None,
+            finish_sp: None,
+            returning: false,
+            resuming: false,
+            last_command: String::new(),
+        }
+    }
+
+    // Stops before the next instruction
+    pub fn request_break(&mut self) {
+        self.enabled = true;
+        self.break_requested = true;
+    }
+
+    // Called for every CPU bus access while enabled
+    pub fn access(&mut self, address: u16, value: u8, access: Access) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+
+        let index = self.watchpoints.iter().position(|watch| {
+            (watch.start..=watch.end).contains(&address)
+                && (watch.access == Access::ReadWrite || watch.access == access)
+        });
+
+        if let Some(index) = index {
+            self.watch_hit = Some(WatchHit {
+                index,
+                address,
+                value,
+                access,
+            });
+        }
+    }
+
+    pub fn should_break(cpu: &mut Cpu) -> bool {
+        let pc = cpu.registers.pc;
+        let opcode = Bus::read(cpu, pc);
+        let dbg = &mut cpu.debugger;
+
+        if let Some(hit) = dbg.watch_hit.take() {
+            let verb = if hit.access == Access::Write {
+                "write"
+            } else {
+                "read"
+            };
+            println!(
+                "Watchpoint {}: {} ${:04X} = ${:02X} by instruction at ${:04X}",
+                hit.index, verb, hit.address, hit.value, dbg.instruction_pc
+            );
+            return dbg.stop();
+        }
+
+        dbg.instruction_pc = pc;
+
+        if dbg.break_requested {
+            return dbg.stop();
+        }
+
+        if std::mem::take(&mut dbg.resuming) {
+            return false;
+        }
+
+        // While halted or stopped the same PC comes up until the CPU wakes
+        if cpu.halted || cpu.stopped {
+            return false;
+        }
+
+        if let Some(sp) = dbg.finish_sp {
+            if dbg.returning && cpu.registers.sp > sp {
+                return dbg.stop();
+            }
+
+            dbg.returning = Self::is_return(opcode);
+        }
+
+        if dbg.steps > 0 {
+            dbg.steps -= 1;
+
+            if dbg.steps == 0 {
+                return dbg.stop();
+            }
+        }
+
+        if dbg.step_over == Some(pc) {
+            return dbg.stop();
+        }
+
+        let hit = cpu.debugger.breakpoints.iter().position(|bp| {
+            bp.address == pc
+                && bp
+                    .condition
+                    .as_ref()
+                    .is_none_or(|condition| Self::holds(cpu, condition))
+        });
+
+        match hit {
+            Some(index) => {
+                println!("Breakpoint {} at ${:04X}", index, pc);
+                cpu.debugger.stop()
+            }
+            None => false,
+        }
+    }
+
+    fn stop(&mut self) -> bool {
+        self.break_requested = false;
+        self.steps = 0;
+        self.step_over = None;
+        self.finish_sp = None;
+        self.returning = false;
+        true
+    }
+
+    fn is_return(opcode: u8) -> bool {
+        matches!(
+            Instruction::instruction_by_opcode(opcode).ins_type,
+            InstructionType::Ret | InstructionType::Reti
+        )
+    }
+
+    fn holds(cpu: &Cpu, condition: &Condition) -> bool {
+        let value = cpu.read_register(condition.register);
+
+        match condition.compare {
+            Compare::Eq => value == condition.value,
+            Compare::Ne => value != condition.value,
+            Compare::Lt => value < condition.value,
+            Compare::Gt => value > condition.value,
+            Compare::Le => value <= condition.value,
+            Compare::Ge => value >= condition.value,
+        }
+    }
+
+    // Reads commands until one resumes execution. False if the emulator
+    // should quit.
+    pub fn repl(cpu: &mut Cpu) -> bool {
+        Self::print_instruction(cpu, cpu.registers.pc, true);
+
+        let stdin = io::stdin();
+
+        loop {
+            print!("(gbdb) ");
+            io::stdout().flush().unwrap();
+
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+                // stdin closed, keep running without the debugger
+                cpu.debugger.enabled = false;
+                return true;
+            }
+
+            let line = match line.trim() {
+                "" => cpu.debugger.last_command.clone(),
+                line => line.to_string(),
+            };
+            cpu.debugger.last_command = line.clone();
+
+            let args: Vec<&str> = line.split_whitespace().collect();
+            if args.is_empty() {
+                continue;
+            }
+
+            match Self::command(cpu, &args) {
+                Ok(Some(resume)) => {
+                    cpu.debugger.resuming = true;
+                    return resume;
+                }
+                Ok(None) => {}
+                Err(err) => println!("{}", err),
+            }
+        }
+    }
+
+    // Some(true) resumes, Some(false) quits, None stays in the REPL
+    fn command(cpu: &mut Cpu, args: &[&str]) -> Result<Option<bool>, String> {
+        let pc = cpu.registers.pc;
+
+        match args[0] {
+            "s" | "step" => {
+                let count = match args.get(1) {
+                    Some(count) => count.parse().map_err(|_| "Invalid count")?,
+                    None => 1,
+                };
+                cpu.debugger.steps = count.max(1);
+                Ok(Some(true))
+            }
+            "n" | "next" => {
+                let opcode = Bus::read(cpu, pc);
+                let ins = Instruction::instruction_by_opcode(opcode);
+
+                match ins.ins_type {
+                    InstructionType::Call | InstructionType::Rst => {
+                        cpu.debugger.step_over = Some(pc.wrapping_add(Disassembler::length(&ins)));
+                    }
+                    _ => cpu.debugger.steps = 1,
+                }
+                Ok(Some(true))
+            }
+            "f" | "finish" => {
+                cpu.debugger.finish_sp = Some(cpu.registers.sp);
+                cpu.debugger.returning = Self::is_return(Bus::read(cpu, pc));
+                Ok(Some(true))
+            }
+            "c" | "continue" => Ok(Some(true)),
+            "q" | "quit" => Ok(Some(false)),
+            "b" | "break" => {
+                let address = Self::number(args.get(1))?;
+                let condition = match args.get(2) {
+                    Some(&"if") => Some(Self::condition(&args[3..])?),
+                    Some(other) => return Err(format!("Expected 'if', got '{}'", other)),
+                    None => None,
+                };
+
+                cpu.debugger
+                    .breakpoints
+                    .push(Breakpoint { address, condition });
+                println!(
+                    "Breakpoint {} at ${:04X}",
+                    cpu.debugger.breakpoints.len() - 1,
+                    address
+                );
+                Ok(None)
+            }
+            "w" | "watch" => {
+                let range = args.get(1).ok_or("Missing address")?;
+                let (start, end) = match range.split_once('-') {
+                    Some((start, end)) => (Self::number(Some(&start))?, Self::number(Some(&end))?),
+                    None => {
+                        let address = Self::number(Some(range))?;
+                        (address, address)
+                    }
+                };
+                let access = match args.get(2) {
+                    Some(&"r") => Access::Read,
+                    Some(&"w") | None => Access::Write,
+                    Some(&"rw") => Access::ReadWrite,
+                    Some(other) => return Err(format!("Unknown access '{}'", other)),
+                };
+
+                cpu.debugger
+                    .watchpoints
+                    .push(Watchpoint { start, end, access });
+                println!(
+                    "Watchpoint {} on ${:04X}-${:04X} ({:?})",
+                    cpu.debugger.watchpoints.len() - 1,
+                    start,
+                    end,
+                    access
+                );
+                Ok(None)
+            }
+            "i" | "info" => {
+                for (index, bp) in cpu.debugger.breakpoints.iter().enumerate() {
+                    match &bp.condition {
+                        Some(condition) => println!(
+                            "Breakpoint {} at ${:04X} if {:?} {:?} ${:X}",
+                            index,
+                            bp.address,
+                            condition.register,
+                            condition.compare,
+                            condition.value
+                        ),
+                        None => println!("Breakpoint {} at ${:04X}", index, bp.address),
+                    }
+                }
+
+                for (index, watch) in cpu.debugger.watchpoints.iter().enumerate() {
+                    println!(
+                        "Watchpoint {} on ${:04X}-${:04X} ({:?})",
+                        index, watch.start, watch.end, watch.access
+                    );
+                }
+                Ok(None)
+            }
+            "delete" => {
+                Self::remove(&mut cpu.debugger.breakpoints, args.get(1))?;
+                Ok(None)
+            }
+            "unwatch" => {
+                Self::remove(&mut cpu.debugger.watchpoints, args.get(1))?;
+                Ok(None)
+            }
+            "r" | "regs" => {
+                Self::print_registers(cpu);
+                Ok(None)
+            }
+            "x" => {
+                let address = Self::number(args.get(1))?;
+                let len = match args.get(2) {
+                    Some(len) => len.parse().map_err(|_| "Invalid length")?,
+                    None => 64,
+                };
+                Self::dump(cpu, address, len);
+                Ok(None)
+            }
+            "set" => {
+                let target = args.get(1).ok_or("Missing register or address")?;
+                let value = Self::number(args.get(2))?;
+
+                match Self::register(target) {
+                    Some(RegisterType::F) => cpu.registers.f = value as u8 & 0xF0,
+                    Some(RegisterType::Af) => cpu.set_register(RegisterType::Af, value & 0xFFF0),
+                    Some(register) => cpu.set_register(register, value),
+                    None => Bus::write(cpu, Self::number(Some(target))?, value as u8),
+                }
+                Ok(None)
+            }
+            "d" | "dis" => {
+                let count = match args.get(2) {
+                    Some(count) => count.parse().map_err(|_| "Invalid count")?,
+                    None => 10,
+                };
+
+                match args.get(1) {
+                    Some(address) => Self::disassemble(cpu, Self::number(Some(address))?, count),
+                    None => Self::disassemble_around(cpu, pc),
+                }
+                Ok(None)
+            }
+            "h" | "help" => {
+                println!("{}", HELP);
+                Ok(None)
+            }
+            other => Err(format!("Unknown command '{}', try help", other)),
+        }
+    }
+
+    fn remove<T>(list: &mut Vec<T>, index: Option<&&str>) -> Result<(), String> {
+        match index {
+            Some(index) => {
+                let index: usize = index.parse().map_err(|_| "Invalid index")?;
+                if index >= list.len() {
+                    return Err(format!("No entry {}", index));
+                }
+                list.remove(index);
+            }
+            None => list.clear(),
+        }
+
+        Ok(())
+    }
+
+    fn number(arg: Option<&&str>) -> Result<u16, String> {
+        let arg = arg.ok_or("Missing value")?;
+        let digits = arg
+            .trim_start_matches('$')
+            .trim_start_matches("0x")
+            .trim_start_matches("0X");
+
+        u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid hex value '{}'", arg))
+    }
+
+    fn register(name: &str) -> Option<RegisterType> {
+        match name.to_lowercase().as_str() {
+            "a" => Some(RegisterType::A),
+            "f" => Some(RegisterType::F),
+            "b" => Some(RegisterType::B),
+            "c" => Some(RegisterType::C),
+            "d" => Some(RegisterType::D),
+            "e" => Some(RegisterType::E),
+            "h" => Some(RegisterType::H),
+            "l" => Some(RegisterType::L),
+            "af" => Some(RegisterType::Af),
+            "bc" => Some(RegisterType::Bc),
+            "de" => Some(RegisterType::De),
+            "hl" => Some(RegisterType::Hl),
+            "sp" => Some(RegisterType::Sp),
+            "pc" => Some(RegisterType::Pc),
+            _ => None,
+        }
+    }
+
+    // <reg> <op> <value>
+    fn condition(args: &[&str]) -> Result<Condition, String> {
+        if args.len() != 3 {
+            return Err(String::from("Condition must be: <reg> <op> <value>"));
+        }
+
+        let register = Self::register(args[0]).ok_or(format!("Unknown register '{}'", args[0]))?;
+        let compare = match args[1] {
+            "==" => Compare::Eq,
+            "!=" => Compare::Ne,
+            "<" => Compare::Lt,
+            ">" => Compare::Gt,
+            "<=" => Compare::Le,
+            ">=" => Compare::Ge,
+            other => return Err(format!("Unknown comparison '{}'", other)),
+        };
+
+        Ok(Condition {
+            register,
+            compare,
+            value: Self::number(Some(&args[2]))?,
+        })
+    }
+
+    fn print_registers(cpu: &Cpu) {
+        let r = &cpu.registers;
+        let flag = |set: bool, name: char| if set { name } else { '-' };
+
+        println!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} [{}{}{}{}]",
+            r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp, r.pc,
+            flag(r.flag_z(), 'Z'), flag(r.flag_n(), 'N'), flag(r.flag_h(), 'H'), flag(r.flag_c(), 'C')
+        );
+        println!(
+            "IME:{} IE:{:02X} IF:{:02X} HALT:{} LY:{:02X} cycles:{}",
+            cpu.int_master_enabled as u8,
+            cpu.ie_register,
+            cpu.interrupt_flags,
+            cpu.halted as u8,
+            cpu.bus.lcd.ly,
+            cpu.timer.ticks
+        );
+    }
+
+    fn dump(cpu: &Cpu, address: u16, len: u32) {
+        for row in (0..len).step_by(16) {
+            let start = address.wrapping_add(row as u16);
+            let bytes: Vec<u8> = (0..16.min(len - row))
+                .map(|i| Bus::read(cpu, start.wrapping_add(i as u16)))
+                .collect();
+
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let text: String = bytes
+                .iter()
+                .map(|&byte| match byte {
+                    0x20..=0x7E => byte as char,
+                    _ => '.',
+                })
+                .collect();
+
+            println!("${:04X}: {:<47}  {}", start, hex.join(" "), text);
+        }
+    }
+
+    fn print_instruction(cpu: &Cpu, address: u16, current: bool) -> u16 {
+        let (text, length) = Disassembler::instruction(&|addr| Bus::read(cpu, addr), address);
+        let bytes: Vec<String> = (0..length)
+            .map(|i| format!("{:02X}", Bus::read(cpu, address.wrapping_add(i))))
+            .collect();
+        let breakpoint = cpu
+            .debugger
+            .breakpoints
+            .iter()
+            .any(|bp| bp.address == address);
+
+        println!(
+            "{}{} ${:04X}: {:<9} {}",
+            if current { "=>" } else { "  " },
+            if breakpoint { '*' } else { ' ' },
+            address,
+            bytes.join(" "),
+            text
+        );
+
+        length
+    }
+
+    fn disassemble(cpu: &Cpu, mut address: u16, count: u32) {
+        for _ in 0..count {
+            let current = address == cpu.registers.pc;
+            address = address.wrapping_add(Self::print_instruction(cpu, address, current));
+        }
+    }
+
+    // Code can't be decoded backwards reliably, so a few bytes before PC are
+    // tried as starting points until one lines up with PC.
+    fn disassemble_around(cpu: &Cpu, pc: u16) {
+        let read = |addr| Bus::read(cpu, addr);
+
+        for back in (1..=12u16).rev() {
+            let mut address = pc.wrapping_sub(back);
+            let mut starts = Vec::new();
+
+            while starts.len() < 12 && address != pc && pc.wrapping_sub(address) <= back {
+                starts.push(address);
+                address = address.wrapping_add(Disassembler::instruction(&read, address).1);
+            }
+
+            if address == pc {
+                let skip = starts.len().saturating_sub(4);
+                for &start in &starts[skip..] {
+                    Self::print_instruction(cpu, start, false);
+                }
+                break;
+            }
+        }
+
+        Self::disassemble(cpu, pc, 6);
+    }
+}
@@ -0,0 +1,452 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    fs,
+    path::Path,
+};
+
+use crate::enums::{
+    address_mode::AddressMode, condition_type::ConditionType, instruction_type::InstructionType,
+    register_type::RegisterType,
+};
+
+use super::instruction::Instruction;
+
+const CB_OPS: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+const CB_REGS: [&str; 8] = ["b", "c", "d", "e", "h", "l", "[hl]", "a"];
+
+const BANK_SIZE: usize = 0x4000;
+
+// Names from an RGBDS .sym file, keyed by bank and address
+pub struct Symbols {
+    names: HashMap<(u16, u16), String>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self {
+            names: HashMap::new(),
+        }
+    }
+
+    // Lines look like "01:4000 Name", ; starts a comment
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let mut symbols = Self::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let parsed = line.split_once(' ').and_then(|(location, name)| {
+                let (bank, address) = location.split_once(':')?;
+                Some((
+                    u16::from_str_radix(bank, 16).ok()?,
+                    u16::from_str_radix(address, 16).ok()?,
+                    name.trim(),
+                ))
+            });
+
+            match parsed {
+                Some((bank, address, name)) => symbols.insert(bank, address, name),
+                None => {
+                    return Err(format!(
+                        "line {}: expected <bank>:<address> <name>",
+                        number + 1
+                    ))
+                }
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    pub fn insert(&mut self, bank: u16, address: u16, name: &str) {
+        self.names.insert((bank, address), name.to_string());
+    }
+
+    // Name of an address as seen from code running in bank, ROM bank 0 and
+    // RAM symbols don't depend on the switched bank
+    pub fn get(&self, bank: u16, address: u16) -> Option<&str> {
+        let bank = match address {
+            0x0000..=0x3FFF => 0,
+            0x4000..=0x7FFF => bank,
+            _ => {
+                return self.get_in(0, address).or_else(|| {
+                    self.names
+                        .iter()
+                        .find(|((_, addr), _)| *addr == address)
+                        .map(|(_, name)| name.as_str())
+                })
+            }
+        };
+
+        self.get_in(bank, address)
+    }
+
+    fn get_in(&self, bank: u16, address: u16) -> Option<&str> {
+        self.names.get(&(bank, address)).map(String::as_str)
+    }
+}
+
+// Turns the opcode metadata in Instruction back into RGBDS syntax
+pub struct Disassembler {}
+
+impl Disassembler {
+    // Text of the instruction at address and its length in bytes, bytes are
+    // fetched through read so this works on the live bus or a ROM image.
+    pub fn instruction(read: &dyn Fn(u16) -> u8, address: u16) -> (String, u16) {
+        Self::decode(read, address, &|_| None)
+    }
+
+    // Like instruction, with jump targets and memory operands replaced by
+    // the name label returns for them
+    pub fn decode(
+        read: &dyn Fn(u16) -> u8,
+        address: u16,
+        label: &dyn Fn(u16) -> Option<String>,
+    ) -> (String, u16) {
+        let opcode = read(address);
+        let ins = Instruction::instruction_by_opcode(opcode);
+        let length = Self::length(&ins);
+
+        let n8 = read(address.wrapping_add(1));
+        let n16 = n8 as u16 | (read(address.wrapping_add(2)) as u16) << 8;
+        let name = |address: u16| label(address).unwrap_or_else(|| format!("${:04X}", address));
+
+        let mnemonic = match ins.ins_type {
+            InstructionType::Undefined | InstructionType::None | InstructionType::Err => {
+                return (format!("db ${:02X}", opcode), 1);
+            }
+            InstructionType::Cb => return (Self::cb(n8), 2),
+            // ld [c], a and ld a, [c] are high page accesses
+            InstructionType::Ld
+                if ins.reg1 == RegisterType::C && ins.addr_mode == AddressMode::MrR
+                    || ins.reg2 == RegisterType::C && ins.addr_mode == AddressMode::RmR =>
+            {
+                String::from("ldh")
+            }
+            other => format!("{:?}", other).to_lowercase(),
+        };
+
+        let reg1 = Self::register(ins.reg1);
+        let reg2 = Self::register(ins.reg2);
+
+        let mut operands: Vec<String> = match ins.addr_mode {
+            AddressMode::Imp => match ins.param {
+                Some(vector) => vec![format!("${:02X}", vector)],
+                None => vec![],
+            },
+            AddressMode::R if ins.reg2 == RegisterType::None => vec![reg1],
+            AddressMode::R | AddressMode::Rr => vec![reg1, reg2],
+            AddressMode::Rd8 if ins.reg1 == RegisterType::Sp => {
+                vec![reg1, (n8 as i8).to_string()]
+            }
+            AddressMode::Rd8 => vec![reg1, format!("${:02X}", n8)],
+            AddressMode::Rd16 => vec![reg1, format!("${:04X}", n16)],
+            AddressMode::MrR => vec![format!("[{}]", reg1), reg2],
+            AddressMode::RmR => vec![reg1, format!("[{}]", reg2)],
+            AddressMode::Rhli => vec![reg1, String::from("[hl+]")],
+            AddressMode::Rhld => vec![reg1, String::from("[hl-]")],
+            AddressMode::HliR => vec![String::from("[hl+]"), reg2],
+            AddressMode::HldR => vec![String::from("[hl-]"), reg2],
+            AddressMode::Ra8 => vec![reg1, format!("[{}]", name(0xFF00 | n8 as u16))],
+            AddressMode::A8R => vec![format!("[{}]", name(0xFF00 | n8 as u16)), reg2],
+            AddressMode::HlSpR => vec![reg1, format!("sp {}", Self::signed(n8))],
+            AddressMode::D16 | AddressMode::D8 => {
+                vec![name(Self::target(read, address).unwrap_or(n16))]
+            }
+            AddressMode::D16R | AddressMode::A16R => vec![format!("[{}]", name(n16)), reg2],
+            AddressMode::MrD8 => vec![format!("[{}]", reg1), format!("${:02X}", n8)],
+            AddressMode::Mr => vec![format!("[{}]", reg1)],
+            AddressMode::Ra16 => vec![reg1, format!("[{}]", name(n16))],
+        };
+
+        if let Some(condition) = Self::condition(ins.cond_type) {
+            operands.insert(0, condition.to_string());
+        }
+
+        if operands.is_empty() {
+            (mnemonic, length)
+        } else {
+            (format!("{} {}", mnemonic, operands.join(", ")), length)
+        }
+    }
+
+    // Where a jp, jr or call with an immediate address goes
+    pub fn target(read: &dyn Fn(u16) -> u8, address: u16) -> Option<u16> {
+        let ins = Instruction::instruction_by_opcode(read(address));
+
+        match (ins.ins_type, ins.addr_mode) {
+            (InstructionType::Jp | InstructionType::Call, AddressMode::D16) => Some(
+                read(address.wrapping_add(1)) as u16 | (read(address.wrapping_add(2)) as u16) << 8,
+            ),
+            // Relative to the next instruction
+            (InstructionType::Jr, AddressMode::D8) => Some(
+                address
+                    .wrapping_add(2)
+                    .wrapping_add(read(address.wrapping_add(1)) as i8 as u16),
+            ),
+            _ => None,
+        }
+    }
+
+    // Listing of a bank of the ROM file at rom_path. Without a sym_path the
+    // .sym file next to the ROM is used if there is one, as RGBDS puts it.
+    pub fn rom_bank(rom_path: &str, bank: u16, sym_path: Option<&str>) -> Result<String, String> {
+        let rom = fs::read(rom_path).map_err(|err| format!("{}: {}", rom_path, err))?;
+
+        let default_sym = Path::new(rom_path).with_extension("sym");
+        let symbols = match sym_path {
+            Some(path) => Symbols::load(path).map_err(|err| format!("{}: {}", path, err))?,
+            None if default_sym.exists() => {
+                let path = default_sym.to_string_lossy();
+                Symbols::load(&path).map_err(|err| format!("{}: {}", path, err))?
+            }
+            None => Symbols::new(),
+        };
+
+        Self::bank(&rom, bank, &symbols)
+    }
+
+    // A whole ROM bank as an RGBDS source listing. Targets of jumps and calls
+    // inside the bank get a label, named from symbols when it has one.
+    pub fn bank(rom: &[u8], bank: u16, symbols: &Symbols) -> Result<String, String> {
+        let banks = rom.len().div_ceil(BANK_SIZE);
+        if bank as usize >= banks.max(1) {
+            return Err(format!(
+                "bank {} is out of range, the ROM has {}",
+                bank, banks
+            ));
+        }
+
+        let offset = bank as usize * BANK_SIZE;
+        let start: u16 = if bank == 0 { 0x0000 } else { 0x4000 };
+        let end = start as u32 + BANK_SIZE as u32;
+
+        // Bytes past the end of the bank (or the file) read as 0xFF
+        let read = |address: u16| {
+            if (start as u32..end).contains(&(address as u32)) {
+                let index = offset + (address - start) as usize;
+                rom.get(index).copied().unwrap_or(0xFF)
+            } else {
+                0xFF
+            }
+        };
+        let in_bank = |address: u16| (start as u32..end).contains(&(address as u32));
+
+        // First pass finds the jump targets that need a label and where
+        // instructions start
+        let mut labels: HashMap<u16, String> = HashMap::new();
+        let mut starts = HashSet::new();
+        let mut address = start as u32;
+
+        while address < end {
+            let pc = address as u16;
+            starts.insert(pc);
+
+            if let Some(target) = Self::target(&read, pc).filter(|&target| in_bank(target)) {
+                let prefix = match Instruction::instruction_by_opcode(read(pc)).ins_type {
+                    InstructionType::Call => "Call",
+                    _ => "Jump",
+                };
+                labels
+                    .entry(target)
+                    .or_insert_with(|| format!("{}_{:03X}_{:04X}", prefix, bank, target));
+            }
+
+            address += Self::instruction(&read, pc).1 as u32;
+        }
+
+        // A label inside an instruction would never be defined
+        let label = |address: u16| {
+            if in_bank(address) && !starts.contains(&address) {
+                return None;
+            }
+
+            symbols
+                .get(bank, address)
+                .map(String::from)
+                .or_else(|| labels.get(&address).cloned())
+        };
+
+        let mut out = String::new();
+        if bank == 0 {
+            writeln!(out, "SECTION \"ROM Bank $000\", ROM0[$0000]").unwrap();
+        } else {
+            writeln!(
+                out,
+                "SECTION \"ROM Bank ${:03X}\", ROMX[$4000], BANK[${:X}]",
+                bank, bank
+            )
+            .unwrap();
+        }
+
+        let mut address = start as u32;
+
+        while address < end {
+            let pc = address as u16;
+
+            if let Some(name) = label(pc) {
+                writeln!(out, "\n{}:", name).unwrap();
+            }
+
+            let (text, length) = Self::decode(&read, pc, &label);
+            // Instructions can't run into the next bank
+            let (text, length) = if address + length as u32 > end {
+                (format!("db ${:02X}", read(pc)), 1)
+            } else {
+                (text, length)
+            };
+
+            let bytes: Vec<String> = (0..length)
+                .map(|i| format!("{:02X}", read(pc + i)))
+                .collect();
+            writeln!(out, "    {:<24} ; ${:04X}: {}", text, pc, bytes.join(" ")).unwrap();
+
+            address += length as u32;
+        }
+
+        Ok(out)
+    }
+
+    pub fn length(ins: &Instruction) -> u16 {
+        match ins.ins_type {
+            InstructionType::Cb | InstructionType::Stop => return 2,
+            _ => {}
+        }
+
+        match ins.addr_mode {
+            AddressMode::Rd8
+            | AddressMode::D8
+            | AddressMode::Ra8
+            | AddressMode::A8R
+            | AddressMode::HlSpR
+            | AddressMode::MrD8 => 2,
+            AddressMode::Rd16
+            | AddressMode::D16
+            | AddressMode::D16R
+            | AddressMode::A16R
+            | AddressMode::Ra16 => 3,
+            _ => 1,
+        }
+    }
+
+    fn cb(op: u8) -> String {
+        let reg = CB_REGS[(op & 7) as usize];
+        let bit = (op >> 3) & 7;
+
+        match op >> 6 {
+            0 => format!("{} {}", CB_OPS[bit as usize], reg),
+            1 => format!("bit {}, {}", bit, reg),
+            2 => format!("res {}, {}", bit, reg),
+            _ => format!("set {}, {}", bit, reg),
+        }
+    }
+
+    // sp + e8 in ld hl, sp + e8
+    fn signed(value: u8) -> String {
+        let value = value as i8;
+
+        if value < 0 {
+            format!("- {}", -(value as i16))
+        } else {
+            format!("+ {}", value)
+        }
+    }
+
+    fn register(reg: RegisterType) -> String {
+        format!("{:?}", reg).to_lowercase()
+    }
+
+    fn condition(cond: ConditionType) -> Option<&'static str> {
+        match cond {
+            ConditionType::None => None,
+            ConditionType::Nz => Some("nz"),
+            ConditionType::Z => Some("z"),
+            ConditionType::Nc => Some("nc"),
+            ConditionType::C => Some("c"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Disassembler, Symbols};
+
+    // Text and length of the instruction at address, with bytes placed
+    // from there on
+    fn decode(address: u16, bytes: &[u8]) -> (String, u16) {
+        let read = |at: u16| {
+            let index = at.wrapping_sub(address) as usize;
+            bytes.get(index).copied().unwrap_or(0)
+        };
+
+        Disassembler::instruction(&read, address)
+    }
+
+    #[test]
+    fn cb_prefix_opcodes() {
+        assert_eq!(decode(0x0000, &[0xCB, 0x7C]), (String::from("bit 7, h"), 2));
+        assert_eq!(decode(0x0000, &[0xCB, 0x37]), (String::from("swap a"), 2));
+        assert_eq!(
+            decode(0x0000, &[0xCB, 0x86]),
+            (String::from("res 0, [hl]"), 2)
+        );
+        assert_eq!(decode(0x0000, &[0xCB, 0xFF]), (String::from("set 7, a"), 2));
+    }
+
+    #[test]
+    fn relative_jumps_show_their_target() {
+        assert_eq!(decode(0x0150, &[0x18, 0xFE]), (String::from("jr $0150"), 2));
+        assert_eq!(
+            decode(0x0150, &[0x20, 0x05]),
+            (String::from("jr nz, $0157"), 2)
+        );
+        assert_eq!(
+            decode(0x4000, &[0x38, 0x80]),
+            (String::from("jr c, $3F82"), 2)
+        );
+    }
+
+    #[test]
+    fn high_page_loads_are_ldh() {
+        assert_eq!(
+            decode(0x0000, &[0xE0, 0x44]),
+            (String::from("ldh [$FF44], a"), 2)
+        );
+        assert_eq!(
+            decode(0x0000, &[0xF0, 0x80]),
+            (String::from("ldh a, [$FF80]"), 2)
+        );
+        assert_eq!(decode(0x0000, &[0xE2]), (String::from("ldh [c], a"), 1));
+        assert_eq!(decode(0x0000, &[0xF2]), (String::from("ldh a, [c]"), 1));
+    }
+
+    #[test]
+    fn bank_listing_labels_jump_targets() {
+        // ld a, $01 / loop: dec a / jr nz, loop / ret
+        let mut rom = vec![0xFF; 0x8000];
+        rom[0x4000..0x4006].copy_from_slice(&[0x3E, 0x01, 0x3D, 0x20, 0xFD, 0xC9]);
+
+        let listing = Disassembler::bank(&rom, 1, &Symbols::new()).unwrap();
+        let lines: Vec<&str> = listing.lines().take(7).collect();
+
+        assert_eq!(
+            lines,
+            [
+                "SECTION \"ROM Bank $001\", ROMX[$4000], BANK[$1]",
+                "    ld a, $01                ; $4000: 3E 01",
+                "",
+                "Jump_001_4002:",
+                "    dec a                    ; $4002: 3D",
+                "    jr nz, Jump_001_4002     ; $4003: 20 FD",
+                "    ret                      ; $4005: C9",
+            ]
+        );
+
+        assert!(Disassembler::bank(&rom, 2, &Symbols::new()).is_err());
+    }
+}
@@ -1,10 +1,18 @@
-use super::{bus::Bus, cpu::Cpu, ppu::Ppu};
+use super::{
+    cart::Cart,
+    cpu::Cpu,
+    ppu::Ppu,
+    ram::Ram,
+    savestate::{StateReader, StateWriter},
+};
 
 pub struct Dma {
     active: bool,
     byte: u8,
     value: u8,
     start_delay: u8,
+    pending: u8,
+    current: u8,
 }
 
 impl Dma {
@@ -14,35 +22,111 @@ impl Dma {
             byte: 0,
             value: 0,
             start_delay: 0,
+            pending: 0,
+            current: 0xFF,
         }
     }
 
+    // A write to 0xFF46 takes one setup cycle before the first byte is
+    // copied. If a transfer is already running it keeps going (and keeps the
+    // bus) until the new one takes over.
     pub fn start(cpu: &mut Cpu, start: u8) {
-        cpu.dma.active = true;
-        cpu.dma.byte = 0;
         cpu.dma.start_delay = 2;
-        cpu.dma.value = start;
+        cpu.dma.pending = start;
     }
 
     pub fn tick(cpu: &mut Cpu) {
-        if !cpu.dma.active {
-            return;
-        }
-
         if cpu.dma.start_delay > 0 {
             cpu.dma.start_delay -= 1;
+
+            if cpu.dma.start_delay == 0 {
+                cpu.dma.active = true;
+                cpu.dma.byte = 0;
+                cpu.dma.value = cpu.dma.pending;
+                return;
+            }
+        }
+
+        if !cpu.dma.active {
             return;
         }
 
-        let value = Bus::read(cpu, cpu.dma.value as u16 * 0x100 + cpu.dma.byte as u16);
+        let value = Self::source_read(cpu, cpu.dma.value as u16 * 0x100 + cpu.dma.byte as u16);
 
+        cpu.dma.current = value;
         Ppu::oam_write(&mut cpu.bus.ppu, cpu.dma.byte as u16, value);
 
         cpu.dma.byte += 1;
         cpu.dma.active = cpu.dma.byte < 0xA0;
     }
 
+    // The DMA unit sits on the memory buses directly, pages 0xE0-0xFF are
+    // not decoded to echo RAM/OAM/IO but wrap around into WRAM.
+    fn source_read(cpu: &Cpu, address: u16) -> u8 {
+        let address = if address >= 0xE000 {
+            address - 0x2000
+        } else {
+            address
+        };
+
+        match address {
+            0x8000..=0x9FFF => Ppu::vram_read(&cpu.bus.ppu, address),
+            0xC000..=0xDFFF => Ram::wram_read(&cpu.bus.ram, address),
+            _ => Cart::read(&cpu.bus.cart, address),
+        }
+    }
+
     pub fn is_trasferring(&self) -> bool {
         self.active
     }
+
+    // While a transfer is running the CPU loses the bus the DMA is reading
+    // from (external bus for ROM/SRAM/WRAM, video bus for VRAM) as well as
+    // OAM. Only IO, HRAM and IE stay reachable.
+    pub fn blocks(&self, address: u16) -> bool {
+        if !self.active {
+            return false;
+        }
+
+        match address {
+            0xFE00..=0xFEFF => true,
+            0xFF00..=0xFFFF => false,
+            _ => Self::on_video_bus(address) == Self::on_video_bus((self.value as u16) << 8),
+        }
+    }
+
+    // What the CPU sees when reading a blocked address: OAM reads return
+    // 0xFF, anything sharing the DMA's bus returns the byte being copied.
+    pub fn conflict_read(&self, address: u16) -> u8 {
+        match address {
+            0xFE00..=0xFEFF => 0xFF,
+            _ => self.current,
+        }
+    }
+
+    fn on_video_bus(address: u16) -> bool {
+        (0x8000..=0x9FFF).contains(&address)
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.active);
+        state.bytes(&[
+            self.byte,
+            self.value,
+            self.start_delay,
+            self.pending,
+            self.current,
+        ]);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.active = state.bool()?;
+        self.byte = state.u8()?;
+        self.value = state.u8()?;
+        self.start_delay = state.u8()?;
+        self.pending = state.u8()?;
+        self.current = state.u8()?;
+
+        Ok(())
+    }
 }
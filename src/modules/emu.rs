@@ -1,11 +1,29 @@
+use crate::modules::bus::Bus;
 use crate::modules::cart::Cart;
+use crate::modules::common::timestamped_path;
 use crate::modules::cpu::Cpu;
 use sdl2::event::{Event, WindowEvent};
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::pixels::Color;
-
-use super::bus::Bus;
+use sdl2::render::{Canvas, TextureCreator};
+use sdl2::ttf::Font;
+use sdl2::video::{Window, WindowContext};
+use std::time::{Duration, Instant};
+
+use super::debug_window::{self, DebugWindow};
+use super::debugger::Debugger;
+use super::joypad::{self, Joypad};
+use super::link_cable::LinkCable;
+use super::movie::Movie;
+use super::options::Options;
+use super::pacer::{Pacer, Pacing, CYCLES_PER_FRAME};
 use super::ppu::Ppu;
+use super::rewind::Rewind;
+use super::savestate::SaveState;
+use super::screenshot::Screenshot;
+use super::test_runner::MOONEYE_BREAKPOINT;
+use super::trace::Tracer;
+use super::video::VideoRecorder;
 
 const SCREEN_WIDTH: u32 = 1024;
 const SCREEN_HEIGHT: u32 = 768;
@@ -16,6 +34,39 @@ const TICKS_PER_LINE: u32 = 456;
 const YRES: i32 = 144;
 const XRES: i32 = 160;
 
+const FONT_PATH: &str = "NotoSansMono-Medium.ttf";
+// How long a message like "Saved slot 1" stays on screen
+const MESSAGE_TIME: Duration = Duration::from_secs(2);
+
+// A rewind state is recorded every this many frames, holding the rewind key
+// steps back one of them per frame.
+const REWIND_INTERVAL: u32 = 2;
+
+// Keyboard layout of the Game Boy buttons
+const BUTTON_KEYS: [(Keycode, u8); 8] = [
+    (Keycode::Right, joypad::RIGHT),
+    (Keycode::Left, joypad::LEFT),
+    (Keycode::Up, joypad::UP),
+    (Keycode::Down, joypad::DOWN),
+    (Keycode::X, joypad::A),
+    (Keycode::Z, joypad::B),
+    (Keycode::Backspace, joypad::SELECT),
+    (Keycode::Return, joypad::START),
+];
+
+// F1-F9 load, Shift+F1-F9 save
+const SLOT_KEYS: [Keycode; 9] = [
+    Keycode::F1,
+    Keycode::F2,
+    Keycode::F3,
+    Keycode::F4,
+    Keycode::F5,
+    Keycode::F6,
+    Keycode::F7,
+    Keycode::F8,
+    Keycode::F9,
+];
+
 const TILE_COLORS: [Color; 4] = [
     Color::RGB(255, 255, 255),
     Color::RGB(175, 175, 175),
@@ -23,10 +74,45 @@ const TILE_COLORS: [Color; 4] = [
     Color::RGB(0, 0, 0),
 ];
 
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Speed {
+    Normal,
+    // N emulated frames per paced frame, only the last one is drawn
+    Fast(u32),
+    // As many frames as the host manages, drawn once per frame period
+    Uncapped,
+    // Every frame held for N frame periods
+    Slow(u32),
+}
+
 pub struct Emu {
     paused: bool,
     running: bool,
     pub die: bool,
+
+    speed: Speed,
+    // Frames left to run while paused
+    advance: u32,
+    fast_forward: Speed,
+    slow_motion: Speed,
+
+    // Save state slots are stored next to the ROM
+    rom_path: String,
+    message: Option<(String, Instant)>,
+
+    rewind: Rewind,
+    rewinding: bool,
+    // The history ran out while rewinding, told once until R is let go
+    rewind_ended: bool,
+
+    // Buttons held on the keyboard
+    buttons: u8,
+    recording: Option<(Movie, String)>,
+    playback: Option<Movie>,
+    playback_frame: usize,
+    video: Option<(VideoRecorder, String)>,
+
+    debug_window: DebugWindow,
 }
 
 impl Emu {
@@ -35,20 +121,436 @@ impl Emu {
             paused: false,
             running: true,
             die: false,
+            speed: Speed::Normal,
+            advance: 0,
+            fast_forward: Speed::Fast(4),
+            slow_motion: Speed::Slow(2),
+            rom_path: String::new(),
+            message: None,
+            rewind: Rewind::new(0, REWIND_INTERVAL),
+            rewinding: false,
+            rewind_ended: false,
+            buttons: 0,
+            recording: None,
+            playback: None,
+            playback_frame: 0,
+            video: None,
+            debug_window: DebugWindow::new(),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.advance = 0;
+    }
+
+    // Pauses if needed and runs exactly one more frame
+    pub fn advance_frame(&mut self) {
+        self.paused = true;
+        self.advance += 1;
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+    }
+
+    // Speeds the F and S hotkeys switch to
+    pub fn set_fast_forward(&mut self, speed: Speed) {
+        self.fast_forward = speed;
+    }
+
+    pub fn set_slow_motion(&mut self, speed: Speed) {
+        self.slow_motion = speed;
+    }
+
+    pub fn toggle_fast_forward(&mut self) {
+        self.speed = if self.speed == self.fast_forward {
+            Speed::Normal
+        } else {
+            self.fast_forward
+        };
+    }
+
+    pub fn toggle_slow_motion(&mut self) {
+        self.speed = if self.speed == self.slow_motion {
+            Speed::Normal
+        } else {
+            self.slow_motion
+        };
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    // Text for the on-screen indicator, nothing at normal speed
+    pub fn status(&self) -> Option<String> {
+        if let Some((message, shown)) = &self.message {
+            if shown.elapsed() < MESSAGE_TIME {
+                return Some(message.clone());
+            }
+        }
+
+        if self.rewinding {
+            return Some(String::from("REWIND"));
+        }
+
+        if self.paused {
+            return Some(String::from("PAUSED"));
+        }
+
+        match self.speed {
+            Speed::Normal if self.recording.is_some() => Some(String::from("REC")),
+            Speed::Normal if self.playback.is_some() => Some(String::from("PLAY")),
+            Speed::Normal => None,
+            Speed::Fast(n) => Some(format!("{}x", n)),
+            Speed::Uncapped => Some(String::from("MAX")),
+            Speed::Slow(n) => Some(format!("1/{}x", n)),
+        }
+    }
+
+    pub fn show_message(&mut self, message: String) {
+        println!("{}", message);
+        self.message = Some((message, Instant::now()));
+    }
+
+    pub fn save_state(&mut self, cpu: &Cpu, slot: u8) {
+        let path = SaveState::slot_path(&self.rom_path, slot);
+
+        match SaveState::save(cpu, &path) {
+            Ok(()) => self.show_message(format!("Saved slot {}", slot)),
+            Err(err) => self.show_message(format!("Save failed: {}", err)),
+        }
+    }
+
+    pub fn load_state(&mut self, cpu: &mut Cpu, slot: u8) {
+        if self.movie_active() {
+            self.show_message(String::from("Can't load a state during a movie"));
+            return;
+        }
+
+        let path = SaveState::slot_path(&self.rom_path, slot);
+
+        match SaveState::load(cpu, &path) {
+            Ok(()) => self.show_message(format!("Loaded slot {}", slot)),
+            Err(err) => self.show_message(format!("Load failed: {}", err)),
+        }
+    }
+
+    // Next to the ROM, at native resolution or as big as the window shows it
+    pub fn screenshot(&mut self, cpu: &Cpu, scale: u32) {
+        let path = Screenshot::default_path(&self.rom_path);
+
+        match Screenshot::save(cpu, &path, scale) {
+            Ok(()) => self.show_message(format!("Saved {}", path)),
+            Err(err) => self.show_message(format!("Screenshot failed: {}", err)),
+        }
+    }
+
+    pub fn movie_active(&self) -> bool {
+        self.recording.is_some() || self.playback.is_some()
+    }
+
+    // Records from the machine as it is now, which is power-on if called
+    // before the first frame.
+    pub fn start_recording(&mut self, cpu: &Cpu, path: &str) {
+        let start_state = if cpu.timer.ticks == 0 {
+            None
+        } else {
+            Some(SaveState::snapshot(cpu))
+        };
+
+        self.recording = Some((Movie::new(start_state), path.to_string()));
+        self.show_message(format!("Recording {}", path));
+    }
+
+    pub fn stop_recording(&mut self, cpu: &Cpu) {
+        if let Some((mut movie, path)) = self.recording.take() {
+            movie.finish(cpu);
+
+            match movie.save(cpu, &path) {
+                Ok(()) => self.show_message(format!(
+                    "Saved {} ({} frames, hash {:016X})",
+                    path,
+                    movie.inputs.len(),
+                    movie.hash
+                )),
+                Err(err) => self.show_message(format!("Saving movie failed: {}", err)),
+            }
+        }
+    }
+
+    // Every frame emulated from now on goes into the video, whatever speed
+    // the emulator runs at
+    pub fn start_video(&mut self, path: &str) {
+        match VideoRecorder::new(path) {
+            Ok(recorder) => {
+                self.video = Some((recorder, path.to_string()));
+                self.show_message(format!("Recording video {}", path));
+            }
+            Err(err) => self.show_message(format!("Can't record video: {}", err)),
+        }
+    }
+
+    pub fn stop_video(&mut self) {
+        if let Some((recorder, path)) = self.video.take() {
+            match recorder.finish() {
+                Ok(frames) => self.show_message(format!("Saved {} ({} frames)", path, frames)),
+                Err(err) => self.show_message(format!("Saving video failed: {}: {}", path, err)),
+            }
+        }
+    }
+
+    fn record_video(&mut self, cpu: &Cpu) {
+        let result = match &mut self.video {
+            Some((recorder, _)) => recorder.frame(cpu),
+            None => return,
+        };
+
+        if let Err(err) = result {
+            self.show_message(format!("Video recording stopped: {}", err));
+            self.stop_video();
+        }
+    }
+
+    // A movie without a start state must be played on a machine that was
+    // just powered on.
+    pub fn start_playback(&mut self, cpu: &mut Cpu, movie: Movie) -> Result<(), String> {
+        if let Some(state) = &movie.start_state {
+            SaveState::restore(cpu, state)?;
+        }
+
+        self.recording = None;
+        self.playback = Some(movie);
+        self.playback_frame = 0;
+        self.update_playback(cpu);
+
+        Ok(())
+    }
+
+    fn update_playback(&mut self, cpu: &Cpu) {
+        let movie = match &self.playback {
+            Some(movie) if self.playback_frame >= movie.inputs.len() => movie,
+            _ => return,
+        };
+
+        let hash = Movie::machine_hash(cpu);
+        let message = if hash == movie.hash {
+            format!("Movie finished, hash {:016X} matches", hash)
+        } else {
+            format!(
+                "Movie desynced, hash {:016X} expected {:016X}",
+                hash, movie.hash
+            )
+        };
+
+        self.playback = None;
+        self.show_message(message);
+    }
+
+    fn handle_key_up(&mut self, keycode: Keycode) {
+        if keycode == Keycode::R {
+            self.set_rewinding(false);
+        }
+
+        if let Some(&(_, button)) = BUTTON_KEYS.iter().find(|(key, _)| *key == keycode) {
+            self.buttons &= !button;
+        }
+    }
+
+    fn handle_key(&mut self, cpu: &mut Cpu, keycode: Keycode, keymod: Mod) {
+        if let Some(&(_, button)) = BUTTON_KEYS.iter().find(|(key, _)| *key == keycode) {
+            self.buttons |= button;
+            return;
+        }
+
+        if let Some(slot) = SLOT_KEYS.iter().position(|&key| key == keycode) {
+            let slot = slot as u8 + 1;
+
+            if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                self.save_state(cpu, slot);
+            } else {
+                self.load_state(cpu, slot);
+            }
+            return;
+        }
+
+        match keycode {
+            Keycode::Escape => self.stop(),
+            Keycode::P => self.toggle_pause(),
+            Keycode::N => self.advance_frame(),
+            Keycode::F => self.toggle_fast_forward(),
+            Keycode::S => self.toggle_slow_motion(),
+            // Rewind runs for as long as R is held
+            Keycode::R => self.set_rewinding(true),
+            // Cycles through the debug window tabs
+            Keycode::Tab if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => {
+                self.debug_window.next_tab(-1)
+            }
+            Keycode::Tab => self.debug_window.next_tab(1),
+            Keycode::F10 if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => {
+                self.screenshot(cpu, SCALE as u32)
+            }
+            Keycode::F10 => self.screenshot(cpu, 1),
+            // Breaks into the debugger on the terminal
+            Keycode::F12 => cpu.debugger.request_break(),
+            Keycode::V if self.video.is_some() => self.stop_video(),
+            Keycode::V => self.start_video(&timestamped_path(&self.rom_path, "avi")),
+            Keycode::M if self.recording.is_some() => self.stop_recording(cpu),
+            Keycode::M if self.playback.is_none() => {
+                let path = Movie::default_path(&self.rom_path);
+                self.start_recording(cpu, &path);
+            }
+            _ => {}
         }
     }
 
-    pub fn run(rom_path: String) {
+    // Runs until the PPU finishes a frame, or for a frame's worth of cycles
+    // while the LCD is off. False once the CPU stopped.
+    fn run_frame(&mut self, cpu: &mut Cpu) -> bool {
+        // Input only changes between frames, which is what makes a movie
+        // one byte per frame.
+        let buttons = match &self.playback {
+            Some(movie) => movie.inputs[self.playback_frame],
+            None => self.buttons,
+        };
+        Joypad::set_buttons(cpu, buttons);
+
+        if let Some((movie, _)) = &mut self.recording {
+            movie.inputs.push(buttons);
+        }
+
+        let start = cpu.timer.ticks;
+        let frame = cpu.bus.ppu.current_frame;
+
+        while cpu.bus.ppu.current_frame == frame && cpu.timer.ticks - start < CYCLES_PER_FRAME {
+            if cpu.debugger.enabled && Debugger::should_break(cpu) && !Debugger::repl(cpu) {
+                return false;
+            }
+
+            if !cpu.step() {
+                eprintln!(
+                    "Illegal opcode {:02X} at {:04X}",
+                    cpu.opcode, cpu.registers.pc
+                );
+                return false;
+            }
+        }
+
+        self.record_video(cpu);
+
+        if self.playback.is_some() {
+            self.playback_frame += 1;
+            self.update_playback(cpu);
+        }
+
+        self.rewind.record(cpu);
+        true
+    }
+
+    // Keeps enough history for the given number of bytes, 0 disables rewind
+    pub fn set_rewind_budget(&mut self, budget: usize) {
+        self.rewind = Rewind::new(budget, REWIND_INTERVAL);
+    }
+
+    pub fn set_rewinding(&mut self, rewinding: bool) {
+        if rewinding && self.movie_active() {
+            self.show_message(String::from("Can't rewind during a movie"));
+            return;
+        }
+
+        self.rewinding = rewinding && self.rewind.enabled();
+        if !rewinding {
+            self.rewind_ended = false;
+        }
+    }
+
+    // Steps back one recorded state. Once the history ran out the machine
+    // stays on the oldest one.
+    pub fn rewind_frame(&mut self, cpu: &mut Cpu) {
+        if self.rewind.step_back(cpu) {
+            self.rewind_ended = false;
+        } else if !self.rewind_ended {
+            self.rewind_ended = true;
+            self.show_message(String::from("Rewind history used up"));
+        }
+    }
+
+    // Emulates the frames for one pass of the main loop and returns how many
+    // frame periods the pacer should wait afterwards.
+    fn run_frames(&mut self, cpu: &mut Cpu) -> u32 {
+        if self.paused {
+            if self.advance > 0 {
+                self.advance -= 1;
+                self.running &= self.run_frame(cpu);
+            }
+
+            return 1;
+        }
+
+        match self.speed {
+            Speed::Normal => {
+                self.running &= self.run_frame(cpu);
+                1
+            }
+            Speed::Fast(n) => {
+                for _ in 0..n {
+                    self.running &= self.run_frame(cpu);
+                }
+                1
+            }
+            Speed::Uncapped => {
+                let deadline = Instant::now() + Pacer::frame_period();
+
+                while self.running && Instant::now() < deadline {
+                    self.running &= self.run_frame(cpu);
+                }
+                0
+            }
+            Speed::Slow(n) => {
+                self.running &= self.run_frame(cpu);
+                n
+            }
+        }
+    }
+
+    // Powered on machine with the ROM loaded
+    pub fn boot(options: &Options, rom_path: &str) -> Cpu {
         let mut cpu = Cpu::new();
-        Cart::load(&mut cpu.bus.cart, &rom_path);
+        Cart::load(&mut cpu.bus.cart, rom_path);
+        cpu.bus.io.serial.cgb = cpu.bus.cart.cgb();
 
         cpu.init();
         Ppu::init(&mut cpu);
         cpu.timer.ticks = 0;
+        cpu.bus.ppu.renderer = options.renderer;
+        cpu.tracer = match Tracer::new(&options.trace) {
+            Ok(tracer) => tracer,
+            Err(err) => {
+                eprintln!("Can't trace: {}", err);
+                std::process::exit(1);
+            }
+        };
+
+        cpu
+    }
+
+    pub fn run(options: Options) {
+        let mut cpu = Self::boot(&options, &options.rom_path);
 
         let sdl_context = sdl2::init().unwrap();
         let video_subsystem = sdl_context.video().unwrap();
-        let _ttf_context = sdl2::ttf::init().unwrap();
+        let ttf_context = sdl2::ttf::init().unwrap();
+        let font = ttf_context.load_font(FONT_PATH, 24).ok();
+        let debug_font = ttf_context.load_font(FONT_PATH, 14).ok();
 
         let main_window = video_subsystem
             .window("Gameboy Emulator", SCREEN_WIDTH, SCREEN_HEIGHT)
@@ -58,14 +560,20 @@ impl Emu {
             .unwrap();
 
         let debug_window = video_subsystem
-            .window("Debug", 584, 864)
+            .window("Debug", debug_window::WIDTH, debug_window::HEIGHT)
             .position_centered()
             .opengl()
             .build()
             .unwrap();
 
-        let mut canvas = main_window.into_canvas().build().unwrap();
+        let mut canvas = if options.pacing == Pacing::Vsync {
+            main_window.into_canvas().present_vsync().build().unwrap()
+        } else {
+            main_window.into_canvas().build().unwrap()
+        };
         let mut dbg_canvas = debug_window.into_canvas().build().unwrap();
+        let texture_creator = canvas.texture_creator();
+        let dbg_texture_creator = dbg_canvas.texture_creator();
 
         canvas.set_draw_color(Color::RGB(0, 0, 0));
         canvas.clear();
@@ -76,77 +584,277 @@ impl Emu {
         dbg_canvas.present();
 
         let mut event_pump = sdl_context.event_pump().unwrap();
+        let mut pacer = Pacer::new(options.pacing, &sdl_context);
+
+        let mut emu = Self::new();
+        emu.set_fast_forward(options.fast_forward);
+        emu.set_slow_motion(options.slow_motion);
+        emu.rom_path = options.rom_path.clone();
+        emu.set_rewind_budget(options.rewind_budget);
+
+        if options.debug {
+            cpu.debugger.request_break();
+        }
+
+        if let Some(path) = &options.record {
+            emu.start_recording(&cpu, path);
+        }
+
+        if let Some(path) = &options.video {
+            emu.start_video(path);
+        }
+
+        if let Some(mode) = &options.link {
+            match LinkCable::open(mode) {
+                Ok(cable) => cpu.bus.io.serial.device = Some(Box::new(cable)),
+                Err(err) => emu.show_message(format!("Can't open link cable: {}", err)),
+            }
+        }
+
+        if let Some(path) = &options.play {
+            match Movie::load(&cpu, path) {
+                Ok(movie) => {
+                    if let Err(err) = emu.start_playback(&mut cpu, movie) {
+                        emu.show_message(format!("Can't play {}: {}", path, err));
+                    }
+                }
+                Err(err) => emu.show_message(format!("Can't play {}: {}", path, err)),
+            }
+        }
 
         let mut prev_frame = 0;
+        let mut prev_blank = false;
+        let mut prev_status = None;
 
-        'gameboyloop: loop {
+        while emu.running {
             for event in event_pump.poll_iter() {
                 match event {
                     Event::Quit { .. }
                     | Event::Window {
                         win_event: WindowEvent::Close,
                         ..
+                    } => emu.stop(),
+                    // The debug window takes keys while it has focus, the
+                    // memory editor uses repeats for moving the cursor
+                    Event::KeyDown {
+                        window_id,
+                        keycode: Some(keycode),
+                        keymod,
+                        repeat,
+                        ..
+                    } => {
+                        let handled = window_id == dbg_canvas.window().id()
+                            && emu.debug_window.key(&mut cpu, keycode, keymod);
+
+                        if !handled && !repeat {
+                            emu.handle_key(&mut cpu, keycode, keymod);
+                        }
                     }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
+                    Event::TextInput {
+                        window_id, text, ..
+                    } if window_id == dbg_canvas.window().id() => {
+                        emu.debug_window.text_input(&mut cpu, &text)
+                    }
+                    Event::KeyUp {
+                        keycode: Some(keycode),
                         ..
-                    } => break 'gameboyloop,
+                    } => emu.handle_key_up(keycode),
+                    Event::MouseButtonDown {
+                        window_id, x, y, ..
+                    } if window_id == dbg_canvas.window().id() => emu.debug_window.click(x, y),
                     _ => {}
                 }
             }
 
-            if !cpu.step() {
-                dbg!("CPU STOPED");
-                break;
-            }
+            // Rewinding shows one state per frame period, also while it
+            // holds on the oldest one
+            let waits = if emu.rewinding {
+                emu.rewind_frame(&mut cpu);
+                1
+            } else {
+                emu.run_frames(&mut cpu)
+            };
+            let status = emu.status();
+
+            // With vsync the present is what paces, so it happens every frame
+            if prev_frame != cpu.bus.ppu.current_frame
+                || prev_blank != cpu.bus.ppu.blank
+                || prev_status != status
+                || pacer.pacing == Pacing::Vsync
+                || emu.debug_window.redraw
+            {
+                Self::update_ui(&cpu, &mut canvas);
+                emu.debug_window.draw(
+                    &cpu,
+                    &mut dbg_canvas,
+                    &dbg_texture_creator,
+                    debug_font.as_ref(),
+                );
+                emu.debug_window.redraw = false;
+
+                if let (Some(font), Some(status)) = (&font, &status) {
+                    Self::draw_status(&mut canvas, &texture_creator, font, status);
+                }
 
-            if prev_frame != cpu.bus.ppu.current_frame {
-                Self::update_ui(&cpu, &mut dbg_canvas, &mut canvas);
+                canvas.present();
                 dbg_canvas.present();
             }
 
             prev_frame = cpu.bus.ppu.current_frame;
+            prev_blank = cpu.bus.ppu.blank;
+            prev_status = status;
+
+            if waits > 0 {
+                pacer.wait(waits);
+            }
         }
+
+        emu.stop_recording(&cpu);
+        emu.stop_video();
     }
 
-    fn update_debug_window(cpu: &Cpu, canvas: &mut sdl2::render::Canvas<sdl2::video::Window>) {
-        let mut x_draw = 0;
-        let mut y_draw = 0;
-        let mut tile_num = 0;
+    // Plays a movie headless and unthrottled, true if the machine ends up
+    // with the hash it was recorded with.
+    pub fn verify(options: &Options, path: &str) -> bool {
+        let mut cpu = Self::boot(options, &options.rom_path);
+        let mut emu = Self::new();
+
+        let movie = match Movie::load(&cpu, path) {
+            Ok(movie) => movie,
+            Err(err) => {
+                println!("Can't play {}: {}", path, err);
+                return false;
+            }
+        };
+        let expected = movie.hash;
 
-        // // make rectangle
-        // let rect = sdl2::rect::Rect::new(0, 0, canvas.window().size().0, canvas.window().size().1);
-        // canvas.set_draw_color(Color::RGB(17, 17, 17));
-        // canvas.fill_rect(rect).unwrap();
+        if let Err(err) = emu.start_playback(&mut cpu, movie) {
+            println!("Can't play {}: {}", path, err);
+            return false;
+        }
 
-        let address = 0x8000;
+        while emu.running && emu.playback.is_some() {
+            emu.running &= emu.run_frame(&mut cpu);
+        }
 
-        // 384 tiles -> 24 * 16
-        for tile_y in 0..24 {
-            for tile_x in 0..16 {
-                Self::display_tile(
-                    cpu,
-                    canvas,
-                    address,
-                    tile_num,
-                    x_draw + (tile_x * SCALE),
-                    y_draw + (tile_y * SCALE),
-                );
+        Movie::machine_hash(&cpu) == expected
+    }
+
+    // Speed indicator in the top right corner of the game screen
+    fn draw_status(
+        canvas: &mut Canvas<Window>,
+        texture_creator: &TextureCreator<WindowContext>,
+        font: &Font,
+        status: &str,
+    ) {
+        let surface = font.render(status).blended(TILE_COLORS[0]).unwrap();
+        let texture = texture_creator
+            .create_texture_from_surface(&surface)
+            .unwrap();
+
+        let x = XRES * SCALE as i32 - surface.width() as i32 - 12;
+        let background = sdl2::rect::Rect::new(x - 6, 6, surface.width() + 12, surface.height());
+
+        canvas.set_draw_color(TILE_COLORS[3]);
+        canvas.fill_rect(background).unwrap();
+        canvas
+            .copy(
+                &texture,
+                None,
+                sdl2::rect::Rect::new(x, 6, surface.width(), surface.height()),
+            )
+            .unwrap();
+    }
 
-                x_draw += 8 * SCALE;
-                tile_num += 1;
+    // Runs the ROM headless and unthrottled for the given number of frames
+    // and reports how long emulating them took.
+    pub fn bench(options: &Options, frames: u32) {
+        let mut cpu = Self::boot(options, &options.rom_path);
+
+        let start = std::time::Instant::now();
+
+        while cpu.bus.ppu.current_frame < frames {
+            if !cpu.step() {
+                break;
             }
+        }
+
+        let elapsed = start.elapsed();
+        let frame_time = elapsed.as_secs_f64() * 1_000_000.0 / frames as f64;
+
+        println!(
+            "{} frames in {:.1} ms: {:.1} us/frame ({:.0} fps)",
+            frames,
+            elapsed.as_secs_f64() * 1000.0,
+            frame_time,
+            1_000_000.0 / frame_time
+        );
+    }
+
+    // Runs the ROM headless for the given number of frames and saves what's
+    // on screen then.
+    pub fn capture(options: &Options, frames: u32) -> Result<String, String> {
+        let mut cpu = Self::boot(options, &options.rom_path);
+        Self::run_headless(options, &mut cpu, frames)?;
+
+        let path = options
+            .screenshot_path
+            .clone()
+            .unwrap_or_else(|| Screenshot::default_path(&options.rom_path));
+        Screenshot::save(&cpu, &path, options.screenshot_scale)?;
+
+        Ok(path)
+    }
+
+    // Runs the ROM headless like capture() and compares the screen against a
+    // reference image. Returns the number of differing pixels and where the
+    // image showing them went.
+    pub fn compare(options: &Options, reference: &str) -> Result<(usize, String), String> {
+        let mut cpu = Self::boot(options, &options.rom_path);
+        Self::run_headless(options, &mut cpu, options.frames)?;
+
+        let diff_path = options
+            .diff_path
+            .clone()
+            .unwrap_or_else(|| Screenshot::diff_path(reference));
+        let differences = Screenshot::compare(&cpu, reference, &diff_path)?;
+
+        Ok((differences, diff_path))
+    }
+
+    // Until the given frame starts, or with --until-ld-bb until the ROM
+    // executes LD B,B if that happens first.
+    fn run_headless(options: &Options, cpu: &mut Cpu, frames: u32) -> Result<(), String> {
+        while cpu.bus.ppu.current_frame < frames {
+            let breakpoint = options.until_breakpoint
+                && !cpu.halted
+                && Bus::read(cpu, cpu.registers.pc) == MOONEYE_BREAKPOINT;
 
-            x_draw = 0;
-            y_draw += 8 * SCALE;
+            if !cpu.step() {
+                return Err(format!(
+                    "illegal opcode {:02X} at {:04X}",
+                    cpu.opcode, cpu.registers.pc
+                ));
+            }
+
+            if breakpoint {
+                break;
+            }
         }
+
+        Ok(())
     }
 
-    fn update_ui(
-        cpu: &Cpu,
-        debug_canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
-        canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
-    ) {
+    fn update_ui(cpu: &Cpu, canvas: &mut sdl2::render::Canvas<sdl2::video::Window>) {
+        if cpu.bus.ppu.blank {
+            let rect =
+                sdl2::rect::Rect::new(0, 0, XRES as u32 * SCALE as u32, YRES as u32 * SCALE as u32);
+
+            canvas.set_draw_color(TILE_COLORS[0]);
+            canvas.fill_rect(rect).unwrap();
+            return;
+        }
+
         let video_buffer = cpu.bus.ppu.video_buffer;
 
         for line_num in 0..YRES {
@@ -167,50 +875,5 @@ impl Emu {
                 canvas.fill_rect(rect).unwrap();
             }
         }
-        canvas.present();
-
-        Self::update_debug_window(cpu, debug_canvas);
-    }
-
-    fn display_tile(
-        cpu: &Cpu,
-        canvas: &mut sdl2::render::Canvas<sdl2::video::Window>,
-        address: u16,
-        tile_num: u16,
-        x: u16,
-        y: u16,
-    ) {
-        for tile_y in (0..16).step_by(2) {
-            let byte1: u8 = Bus::read(cpu, address + (tile_num * 16) + tile_y);
-            let byte2: u8 = Bus::read(cpu, address + (tile_num * 16) + tile_y + 1);
-
-            for bit in (0..7).rev() {
-                let hi = (((byte1 & (1 << bit)) != 0) as u8) << 1;
-                let lo = ((byte2 & (1 << bit)) != 0) as u8;
-
-                let color = TILE_COLORS[(hi | lo) as usize];
-
-                // draw rectangle
-                let rect_x: i32 = (x + (7 - bit) * SCALE) as i32;
-                let rect_y = (y + tile_y / 2 * SCALE) as i32;
-                let rect_w = SCALE as u32;
-                let rect_h = SCALE as u32;
-
-                let rect = sdl2::rect::Rect::new(rect_x, rect_y, rect_w, rect_h);
-                canvas.set_draw_color(color);
-                canvas.fill_rect(rect).unwrap();
-            }
-        }
-    }
-
-    pub fn delay(ms: u64) {
-        std::thread::sleep(std::time::Duration::from_millis(ms));
-    }
-
-    pub fn get_ticks() -> u64 {
-        let now = std::time::SystemTime::now();
-        now.duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64
     }
 }
@@ -1,7 +1,7 @@
 pub mod interrupt {
     use crate::{
         enums::interrupt_types::InterruptType,
-        modules::{cpu::Cpu, stack::Stack},
+        modules::{cpu::Cpu, stack::Stack, timer::Timer},
     };
 
     fn map_interrupt_type_to_u8(interrupt_type: InterruptType) -> u8 {
@@ -18,9 +18,13 @@ pub mod interrupt {
         cpu.interrupt_flags |= map_interrupt_type_to_u8(interrupt_type);
     }
 
+    // Dispatch takes 5 M-cycles: two wait states, the two PC pushes and a
+    // final cycle to load the vector into PC.
     fn process(cpu: &mut Cpu, address: u16) {
+        Timer::cycles(cpu, 2);
         Stack::push16(cpu, cpu.registers.pc);
         cpu.registers.pc = address;
+        Timer::cycles(cpu, 1);
     }
 
     fn check(cpu: &mut Cpu, address: u16, interrupt_type: InterruptType) -> bool {
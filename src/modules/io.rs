@@ -1,41 +1,45 @@
 use crate::modules::{cpu::Cpu, timer::Timer};
 
+use super::joypad::Joypad;
 use super::lcd::Lcd;
+use super::savestate::{StateReader, StateWriter};
+use super::serial::Serial;
 
 pub struct IO {
-    pub serial_data: [u8; 2],
+    pub serial: Serial,
 }
 
 impl IO {
     pub fn new() -> Self {
         Self {
-            serial_data: [0; 2],
+            serial: Serial::new(),
         }
     }
 
     pub fn read(cpu: &Cpu, address: u16) -> u8 {
         match address {
-            0xFF01 => cpu.bus.io.serial_data[0],
-            0xFF02 => cpu.bus.io.serial_data[1],
+            0xFF00 => cpu.bus.joypad.read(),
+            0xFF01 | 0xFF02 => Serial::read(cpu, address),
             0xFF04..=0xFF07 => Timer::read(cpu, address),
-            0xFF0F => cpu.interrupt_flags,
+            // Only the 5 interrupt bits exist, the rest read as 1
+            0xFF0F => cpu.interrupt_flags | 0xE0,
             0xFF40..=0xFF4B => Lcd::read(cpu, address),
             _ => {
                 // println!("Address is not implemented for IO read: {:X}", address);
-                0
+                0xFF
             }
         }
     }
 
     pub fn write(cpu: &mut Cpu, address: u16, value: u8) {
         match address {
-            0xFF01 => cpu.bus.io.serial_data[0] = value,
-            0xFF02 => cpu.bus.io.serial_data[1] = value,
+            0xFF00 => Joypad::write(cpu, value),
+            0xFF01 | 0xFF02 => Serial::write(cpu, address, value),
             0xFF04..=0xFF07 => {
                 Timer::write(cpu, address, value);
             }
             0xFF0F => {
-                cpu.interrupt_flags = value;
+                cpu.interrupt_flags = value & 0x1F;
             }
             0xFF40..=0xFF4B => {
                 Lcd::write(cpu, address, value);
@@ -45,4 +49,12 @@ impl IO {
             }
         };
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        self.serial.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.serial.load_state(state)
+    }
 }
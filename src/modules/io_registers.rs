@@ -0,0 +1,252 @@
+use super::{bus::Bus, cpu::Cpu, lcd::LCDMode};
+
+// Addresses and names of the IO registers, in address order
+pub const REGISTERS: [(u16, &str); 41] = [
+    (0xFF00, "P1"),
+    (0xFF01, "SB"),
+    (0xFF02, "SC"),
+    (0xFF04, "DIV"),
+    (0xFF05, "TIMA"),
+    (0xFF06, "TMA"),
+    (0xFF07, "TAC"),
+    (0xFF0F, "IF"),
+    (0xFF10, "NR10"),
+    (0xFF11, "NR11"),
+    (0xFF12, "NR12"),
+    (0xFF13, "NR13"),
+    (0xFF14, "NR14"),
+    (0xFF16, "NR21"),
+    (0xFF17, "NR22"),
+    (0xFF18, "NR23"),
+    (0xFF19, "NR24"),
+    (0xFF1A, "NR30"),
+    (0xFF1B, "NR31"),
+    (0xFF1C, "NR32"),
+    (0xFF1D, "NR33"),
+    (0xFF1E, "NR34"),
+    (0xFF20, "NR41"),
+    (0xFF21, "NR42"),
+    (0xFF22, "NR43"),
+    (0xFF23, "NR44"),
+    (0xFF24, "NR50"),
+    (0xFF25, "NR51"),
+    (0xFF26, "NR52"),
+    (0xFF40, "LCDC"),
+    (0xFF41, "STAT"),
+    (0xFF42, "SCY"),
+    (0xFF43, "SCX"),
+    (0xFF44, "LY"),
+    (0xFF45, "LYC"),
+    (0xFF46, "DMA"),
+    (0xFF47, "BGP"),
+    (0xFF48, "OBP0"),
+    (0xFF49, "OBP1"),
+    (0xFF4A, "WY"),
+    (0xFF4B, "WX"),
+];
+
+const INTERRUPTS: [&str; 5] = ["VBlank", "STAT", "Timer", "Serial", "Joypad"];
+
+pub struct IoRegister {
+    pub address: u16,
+    pub name: &'static str,
+    pub value: u8,
+    // Decoded bits, one entry per line
+    pub fields: Vec<String>,
+}
+
+// Snapshot of the IO registers with their bits spelled out, for the debug
+// window. IE isn't in the IO range but belongs with IF.
+pub struct IoRegisters {}
+
+impl IoRegisters {
+    pub fn read(cpu: &Cpu) -> Vec<IoRegister> {
+        let mut registers: Vec<IoRegister> = REGISTERS
+            .iter()
+            .map(|&(address, name)| {
+                let value = Bus::read(cpu, address);
+
+                IoRegister {
+                    address,
+                    name,
+                    value,
+                    fields: Self::fields(cpu, address, value),
+                }
+            })
+            .collect();
+
+        registers.push(IoRegister {
+            address: 0xFFFF,
+            name: "IE",
+            value: cpu.ie_register,
+            fields: vec![Self::interrupts(cpu.ie_register)],
+        });
+
+        registers
+    }
+
+    fn fields(cpu: &Cpu, address: u16, value: u8) -> Vec<String> {
+        let bit = |bit: u8| value & (1 << bit) != 0;
+        let on = |bit: u8| if value & (1 << bit) != 0 { "on" } else { "off" };
+        let lcd = &cpu.bus.lcd;
+
+        let fields = match address {
+            // A 0 selects a row and reports a pressed button
+            0xFF00 => format!(
+                "buttons {}, d-pad {}, lines {:04b}",
+                if bit(5) { "off" } else { "on" },
+                if bit(4) { "off" } else { "on" },
+                value & 0x0F
+            ),
+            0xFF01 => match value {
+                0x20..=0x7E => format!("'{}'", value as char),
+                _ => String::new(),
+            },
+            0xFF02 => format!(
+                "transfer {}, {} clock",
+                on(7),
+                if bit(0) { "internal" } else { "external" }
+            ),
+            0xFF04 => format!("counter ${:04X}", cpu.timer.div),
+            0xFF07 => format!(
+                "timer {}, {} Hz",
+                on(2),
+                [4096, 262144, 65536, 16384][(value & 0b11) as usize]
+            ),
+            0xFF0F => Self::interrupts(value),
+
+            // Sound
+            0xFF10 => format!(
+                "sweep pace {}, {}, step {}",
+                (value >> 4) & 0b111,
+                if bit(3) { "down" } else { "up" },
+                value & 0b111
+            ),
+            0xFF11 | 0xFF16 => format!(
+                "duty {}%, length {}",
+                ["12.5", "25", "50", "75"][(value >> 6) as usize],
+                value & 0x3F
+            ),
+            0xFF12 | 0xFF17 | 0xFF21 => format!(
+                "volume {}, {}, pace {}",
+                value >> 4,
+                if bit(3) { "up" } else { "down" },
+                value & 0b111
+            ),
+            0xFF13 | 0xFF18 | 0xFF1D => String::from("period low"),
+            0xFF14 | 0xFF19 | 0xFF1E => format!(
+                "trigger {}, length {}, period high {}",
+                bit(7) as u8,
+                on(6),
+                value & 0b111
+            ),
+            0xFF1A => format!("DAC {}", on(7)),
+            0xFF1B => format!("length {}", value),
+            0xFF1C => format!(
+                "output {}",
+                ["mute", "100%", "50%", "25%"][((value >> 5) & 0b11) as usize]
+            ),
+            0xFF20 => format!("length {}", value & 0x3F),
+            0xFF22 => format!(
+                "shift {}, {}-bit, divider {}",
+                value >> 4,
+                if bit(3) { 7 } else { 15 },
+                value & 0b111
+            ),
+            0xFF23 => format!("trigger {}, length {}", bit(7) as u8, on(6)),
+            0xFF24 => format!("left {}, right {}", (value >> 4) & 0b111, value & 0b111),
+            0xFF25 => format!(
+                "left {}, right {}",
+                Self::channels(value >> 4),
+                Self::channels(value & 0x0F)
+            ),
+            0xFF26 => format!("APU {}, playing {}", on(7), Self::channels(value & 0x0F)),
+
+            // LCD
+            0xFF40 => {
+                return vec![
+                    format!(
+                        "LCD {}, window {} map ${:04X}, tiles ${:04X}",
+                        on(7),
+                        on(5),
+                        lcd.window_map_area(),
+                        lcd.bgw_data_area()
+                    ),
+                    format!(
+                        "BG/window {} map ${:04X}, OBJ {} 8x{}",
+                        on(0),
+                        lcd.bg_map_area(),
+                        on(1),
+                        lcd.obj_height()
+                    ),
+                ];
+            }
+            0xFF41 => {
+                let mode = match lcd.get_lcds_mode() {
+                    LCDMode::Hblank => "HBlank",
+                    LCDMode::Vblank => "VBlank",
+                    LCDMode::Oam => "OAM scan",
+                    LCDMode::Xfer => "drawing",
+                };
+                let sources: Vec<&str> = [(6, "LYC"), (5, "OAM"), (4, "VBlank"), (3, "HBlank")]
+                    .iter()
+                    .filter(|(source, _)| bit(*source))
+                    .map(|(_, name)| *name)
+                    .collect();
+
+                format!(
+                    "mode {}, LY=LYC {}, interrupts {}",
+                    mode,
+                    bit(2) as u8,
+                    if sources.is_empty() {
+                        String::from("none")
+                    } else {
+                        sources.join(" ")
+                    }
+                )
+            }
+            0xFF44 | 0xFF45 if lcd.ly == lcd.lyc => String::from("LY=LYC"),
+            0xFF46 => format!("source ${:02X}00", value),
+            // Shade of color ids 0-3
+            0xFF47..=0xFF49 => format!(
+                "shades {} {} {} {}",
+                value & 0b11,
+                (value >> 2) & 0b11,
+                (value >> 4) & 0b11,
+                value >> 6
+            ),
+            0xFF4B => format!("screen x {}", value as i16 - 7),
+            _ => String::new(),
+        };
+
+        vec![fields]
+    }
+
+    fn interrupts(value: u8) -> String {
+        let set: Vec<&str> = INTERRUPTS
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| value & (1 << bit) != 0)
+            .map(|(_, name)| *name)
+            .collect();
+
+        if set.is_empty() {
+            String::from("none")
+        } else {
+            set.join(" ")
+        }
+    }
+
+    // Sound channels 1-4 from the low bits, "-" for the ones not set
+    fn channels(bits: u8) -> String {
+        (0..4)
+            .map(|channel| {
+                if bits & (1 << channel) != 0 {
+                    char::from(b'1' + channel)
+                } else {
+                    '-'
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,86 @@
+use crate::enums::interrupt_types::InterruptType;
+
+use super::{
+    cpu::Cpu,
+    interrupts::interrupt,
+    savestate::{StateReader, StateWriter},
+};
+
+// Bit of each button in the mask passed to set_buttons, the low nibble is
+// the d-pad and the high nibble the buttons, in P1 order.
+pub const RIGHT: u8 = 1 << 0;
+pub const LEFT: u8 = 1 << 1;
+pub const UP: u8 = 1 << 2;
+pub const DOWN: u8 = 1 << 3;
+pub const A: u8 = 1 << 4;
+pub const B: u8 = 1 << 5;
+pub const SELECT: u8 = 1 << 6;
+pub const START: u8 = 1 << 7;
+
+pub struct Joypad {
+    // P1 bits 4-5 as written by the game, a 0 selects that row
+    select: u8,
+    // Pressed buttons, set by the frontend (or a movie) once per frame
+    buttons: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self {
+            select: 0x30,
+            buttons: 0,
+        }
+    }
+
+    // P1 lower nibble, 0 for a pressed button in a selected row
+    fn lines(&self) -> u8 {
+        let mut pressed = 0;
+
+        if self.select & 0x10 == 0 {
+            pressed |= self.buttons & 0x0F;
+        }
+
+        if self.select & 0x20 == 0 {
+            pressed |= self.buttons >> 4;
+        }
+
+        !pressed & 0x0F
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    pub fn write(cpu: &mut Cpu, value: u8) {
+        let lines = cpu.bus.joypad.lines();
+        cpu.bus.joypad.select = value & 0x30;
+        Self::update_lines(cpu, lines);
+    }
+
+    pub fn buttons(&self) -> u8 {
+        self.buttons
+    }
+
+    pub fn set_buttons(cpu: &mut Cpu, buttons: u8) {
+        let lines = cpu.bus.joypad.lines();
+        cpu.bus.joypad.buttons = buttons;
+        Self::update_lines(cpu, lines);
+    }
+
+    // The interrupt is requested when any of the input lines goes low
+    fn update_lines(cpu: &mut Cpu, old_lines: u8) {
+        if old_lines & !cpu.bus.joypad.lines() != 0 {
+            interrupt::request(cpu, InterruptType::Joybad);
+        }
+    }
+
+    // Only the select bits belong to the machine, the buttons are host input
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.select);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.select = state.u8()? & 0x30;
+        Ok(())
+    }
+}
@@ -2,7 +2,13 @@ use sdl2::pixels::Color;
 
 use crate::enums::interrupt_types::InterruptType;
 
-use super::{cpu::Cpu, dma::Dma, interrupts::interrupt};
+use super::{
+    cpu::Cpu,
+    dma::Dma,
+    interrupts::interrupt,
+    ppu::Ppu,
+    savestate::{StateReader, StateWriter},
+};
 
 const TILE_COLORS: [Color; 4] = [
     Color::RGB(255, 255, 255),
@@ -27,6 +33,7 @@ pub struct Lcd {
     pub wx: u8,    // FF4B
 
     // Other data ..
+    stat_line: bool,
     bg_colors: [Color; 4],
     sp1_colors: [Color; 4],
     sp2_colors: [Color; 4],
@@ -47,6 +54,7 @@ impl Lcd {
             objp1: 0,
             wy: 0,
             wx: 0,
+            stat_line: false,
             bg_colors: [Color::RGB(0, 0, 0); 4],
             sp1_colors: [Color::RGB(0, 0, 0); 4],
             sp2_colors: [Color::RGB(0, 0, 0); 4],
@@ -128,18 +136,44 @@ impl Lcd {
 
     pub fn increment_ly(cpu: &mut Cpu) {
         cpu.bus.lcd.ly += 1;
+    }
 
-        if cpu.bus.lcd.ly == cpu.bus.lcd.lyc {
-            cpu.bus.lcd.set_lyc(1);
+    // All STAT sources are ORed into a single interrupt line and LcdStat is
+    // only requested on its rising edge, so a source becoming true while
+    // another one already holds the line high does not interrupt again.
+    // Must be called after every change to LY, LYC, the mode or STAT.
+    pub fn update_stat(cpu: &mut Cpu) {
+        let lcd = &mut cpu.bus.lcd;
 
-            if cpu.bus.lcd.lcds & StatSrc::Lyc as u8 != 0 {
-                interrupt::request(cpu, InterruptType::LcdStat);
-            }
-        } else {
-            cpu.bus.lcd.set_lyc(0);
+        if lcd.lcd_enabled() == 0 {
+            return;
+        }
+
+        let coincidence = lcd.ly == lcd.lyc;
+        lcd.set_lyc(coincidence as u8);
+
+        let mode = lcd.get_lcds_mode();
+
+        // The mode 2 source also fires when entering VBlank on line 144
+        let oam = mode == LCDMode::Oam || (mode == LCDMode::Vblank && lcd.ly == 144);
+
+        let line = (coincidence && lcd.stat_interrupt(StatSrc::Lyc) != 0)
+            || (mode == LCDMode::Hblank && lcd.stat_interrupt(StatSrc::Hblank) != 0)
+            || (mode == LCDMode::Vblank && lcd.stat_interrupt(StatSrc::Vblank) != 0)
+            || (oam && lcd.stat_interrupt(StatSrc::Oam) != 0);
+
+        let rising = line && !lcd.stat_line;
+        lcd.stat_line = line;
+
+        if rising {
+            interrupt::request(cpu, InterruptType::LcdStat);
         }
     }
 
+    pub fn reset_stat_line(&mut self) {
+        self.stat_line = false;
+    }
+
     pub fn init(&mut self) {
         self.lcdc = 0x91;
         self.scx = 0x00;
@@ -154,11 +188,9 @@ impl Lcd {
         self.wy = 0x00;
         self.wx = 0x00;
 
-        for (i, color) in TILE_COLORS.iter().enumerate() {
-            self.bg_colors[i] = *color;
-            self.sp1_colors[i] = *color;
-            self.sp2_colors[i] = *color;
-        }
+        self.update_pallete(self.bgp, 0);
+        self.update_pallete(self.objp0, 1);
+        self.update_pallete(self.objp1, 2);
     }
 
     pub fn read(cpu: &Cpu, address: u16) -> u8 {
@@ -166,7 +198,7 @@ impl Lcd {
 
         match offset {
             0x00 => cpu.bus.lcd.lcdc,
-            0x01 => cpu.bus.lcd.lcds,
+            0x01 => cpu.bus.lcd.lcds | 0b1000_0000,
             0x02 => cpu.bus.lcd.scy,
             0x03 => cpu.bus.lcd.scx,
             0x04 => cpu.bus.lcd.ly,
@@ -183,13 +215,15 @@ impl Lcd {
 
     pub fn write(cpu: &mut Cpu, address: u16, value: u8) {
         let offset = address - 0xFF40;
+        let was_enabled = cpu.bus.lcd.lcd_enabled() != 0;
 
         match offset {
             0x00 => cpu.bus.lcd.lcdc = value,
-            0x01 => cpu.bus.lcd.lcds = value,
+            // Mode and coincidence bits are read-only
+            0x01 => cpu.bus.lcd.lcds = (cpu.bus.lcd.lcds & 0b0000_0111) | (value & 0b0111_1000),
             0x02 => cpu.bus.lcd.scy = value,
             0x03 => cpu.bus.lcd.scx = value,
-            0x04 => cpu.bus.lcd.ly = value,
+            0x04 => (), // LY is read-only
             0x05 => cpu.bus.lcd.lyc = value,
             0x06 => cpu.bus.lcd.dma = value,
             0x07 => cpu.bus.lcd.bgp = value,
@@ -200,6 +234,20 @@ impl Lcd {
             _ => unreachable!(),
         }
 
+        if offset == 0x00 {
+            let enabled = cpu.bus.lcd.lcd_enabled() != 0;
+
+            if was_enabled && !enabled {
+                Ppu::lcd_off(cpu);
+            } else if !was_enabled && enabled {
+                Ppu::lcd_on(cpu);
+            }
+        }
+
+        if offset == 0x01 || offset == 0x05 {
+            Self::update_stat(cpu);
+        }
+
         if offset == 0x06 {
             // 0xFF46 -> DMA
             Dma::start(cpu, value);
@@ -215,19 +263,59 @@ impl Lcd {
     }
 
     fn update_pallete(&mut self, pdata: u8, pal: u8) {
-        match pal {
-            1 => self.bg_colors = self.sp1_colors,
-            2 => self.bg_colors = self.sp2_colors,
-            _ => (),
+        let colors = match pal {
+            1 => &mut self.sp1_colors,
+            2 => &mut self.sp2_colors,
+            _ => &mut self.bg_colors,
+        };
+
+        colors[0] = TILE_COLORS[(pdata & 0b0000_0011) as usize];
+        colors[1] = TILE_COLORS[((pdata >> 2) & 0b0000_0011) as usize];
+        colors[2] = TILE_COLORS[((pdata >> 4) & 0b0000_0011) as usize];
+        colors[3] = TILE_COLORS[((pdata >> 6) & 0b0000_0011) as usize];
+    }
+
+    pub fn bg_color(&self, color: u8) -> Color {
+        self.bg_colors[color as usize]
+    }
+
+    // palette: 0 for OBP0, 1 for OBP1
+    pub fn obj_color(&self, palette: u8, color: u8) -> Color {
+        if palette == 0 {
+            self.sp1_colors[color as usize]
+        } else {
+            self.sp2_colors[color as usize]
         }
+    }
 
-        self.bg_colors[0] = TILE_COLORS[(pdata & 0b0000_0011) as usize];
-        self.bg_colors[1] = TILE_COLORS[((pdata >> 2) & 0b0000_0011) as usize];
-        self.bg_colors[2] = TILE_COLORS[((pdata >> 4) & 0b0000_0011) as usize];
-        self.bg_colors[3] = TILE_COLORS[((pdata >> 6) & 0b0000_0011) as usize];
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&[
+            self.lcdc, self.lcds, self.scy, self.scx, self.ly, self.lyc, self.dma, self.bgp,
+            self.objp0, self.objp1, self.wy, self.wx,
+        ]);
+        state.bool(self.stat_line);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let mut regs = [0; 12];
+        state.bytes(&mut regs)?;
+
+        [
+            self.lcdc, self.lcds, self.scy, self.scx, self.ly, self.lyc, self.dma, self.bgp,
+            self.objp0, self.objp1, self.wy, self.wx,
+        ] = regs;
+        self.stat_line = state.bool()?;
+
+        // The colors are derived from the palette registers
+        self.update_pallete(self.bgp, 0);
+        self.update_pallete(self.objp0 & 0b1111_1100, 1);
+        self.update_pallete(self.objp1 & 0b1111_1100, 2);
+
+        Ok(())
     }
 }
 
+#[derive(PartialEq, Clone, Copy)]
 pub enum LCDMode {
     Hblank,
     Vblank,
@@ -235,9 +323,130 @@ pub enum LCDMode {
     Xfer,
 }
 
+#[derive(Clone, Copy)]
 pub enum StatSrc {
     Hblank = (1 << 3),
     Vblank = (1 << 4),
     Oam = (1 << 5),
     Lyc = (1 << 6),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{LCDMode, Lcd, StatSrc};
+    use crate::modules::{cpu::Cpu, ppu::Ppu};
+
+    const STAT_IF: u8 = 0x02;
+
+    // LCD on in the given mode and line, with the given STAT sources
+    fn lcd(mode: LCDMode, ly: u8, sources: &[StatSrc]) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.bus.lcd.lcdc = 0x80;
+        cpu.bus.lcd.ly = ly;
+        cpu.bus.lcd.lyc = 0xFF;
+        cpu.bus.lcd.set_lcds_mode(mode);
+        for &source in sources {
+            cpu.bus.lcd.lcds |= source as u8;
+        }
+        cpu
+    }
+
+    // Whether the STAT interrupt was requested since the last call
+    fn requested(cpu: &mut Cpu) -> bool {
+        let requested = cpu.interrupt_flags & STAT_IF != 0;
+        cpu.interrupt_flags &= !STAT_IF;
+        requested
+    }
+
+    #[test]
+    fn each_source_interrupts_on_its_own() {
+        let cases = [
+            (LCDMode::Hblank, 10, StatSrc::Hblank),
+            (LCDMode::Vblank, 150, StatSrc::Vblank),
+            (LCDMode::Oam, 10, StatSrc::Oam),
+            // The mode 2 source also fires entering VBlank
+            (LCDMode::Vblank, 144, StatSrc::Oam),
+        ];
+
+        for (mode, ly, source) in cases {
+            let mut cpu = lcd(mode, ly, &[source]);
+            Lcd::update_stat(&mut cpu);
+            assert!(requested(&mut cpu));
+        }
+
+        // But not on the later VBlank lines
+        let mut cpu = lcd(LCDMode::Vblank, 145, &[StatSrc::Oam]);
+        Lcd::update_stat(&mut cpu);
+        assert!(!requested(&mut cpu));
+    }
+
+    #[test]
+    fn line_held_high_blocks_the_next_source() {
+        let mut cpu = lcd(LCDMode::Hblank, 10, &[StatSrc::Hblank, StatSrc::Oam]);
+        Lcd::update_stat(&mut cpu);
+        assert!(requested(&mut cpu));
+
+        // HBlank straight into mode 2 keeps the line high
+        cpu.bus.lcd.ly = 11;
+        cpu.bus.lcd.set_lcds_mode(LCDMode::Oam);
+        Lcd::update_stat(&mut cpu);
+        assert!(!requested(&mut cpu));
+
+        // Low during mode 3, so the next HBlank interrupts again
+        cpu.bus.lcd.set_lcds_mode(LCDMode::Xfer);
+        Lcd::update_stat(&mut cpu);
+        cpu.bus.lcd.set_lcds_mode(LCDMode::Hblank);
+        Lcd::update_stat(&mut cpu);
+        assert!(requested(&mut cpu));
+    }
+
+    #[test]
+    fn lyc_match_is_blocked_while_another_source_holds_the_line() {
+        let mut cpu = lcd(LCDMode::Hblank, 10, &[StatSrc::Hblank, StatSrc::Lyc]);
+        Lcd::update_stat(&mut cpu);
+        assert!(requested(&mut cpu));
+
+        Lcd::write(&mut cpu, 0xFF45, 10);
+        assert_eq!(cpu.bus.lcd.lyc(), 0b100);
+        assert!(!requested(&mut cpu));
+
+        // Without HBlank holding it, the match interrupts
+        let mut cpu = lcd(LCDMode::Xfer, 10, &[StatSrc::Hblank, StatSrc::Lyc]);
+        Lcd::update_stat(&mut cpu);
+        Lcd::write(&mut cpu, 0xFF45, 10);
+        assert!(requested(&mut cpu));
+
+        // And writing STAT while it holds doesn't interrupt again
+        Lcd::write(&mut cpu, 0xFF41, StatSrc::Lyc as u8 | StatSrc::Oam as u8);
+        assert!(!requested(&mut cpu));
+    }
+
+    #[test]
+    fn ly_reads_0_four_dots_into_line_153() {
+        let mut cpu = lcd(LCDMode::Vblank, 153, &[StatSrc::Lyc]);
+        cpu.bus.lcd.lyc = 0;
+
+        for _ in 0..3 {
+            Ppu::tick(&mut cpu);
+        }
+        assert_eq!(cpu.bus.lcd.ly, 153);
+        assert!(!requested(&mut cpu));
+
+        Ppu::tick(&mut cpu);
+        assert_eq!(cpu.bus.ppu.line_ticks, 4);
+        assert_eq!(cpu.bus.lcd.ly, 0);
+        assert!(requested(&mut cpu));
+
+        // The VBlank source holds the line, so LYC=0 doesn't interrupt
+        let mut cpu = lcd(LCDMode::Vblank, 153, &[StatSrc::Lyc, StatSrc::Vblank]);
+        cpu.bus.lcd.lyc = 0;
+        Lcd::update_stat(&mut cpu);
+        assert!(requested(&mut cpu));
+
+        for _ in 0..4 {
+            Ppu::tick(&mut cpu);
+        }
+        assert_eq!(cpu.bus.lcd.ly, 0);
+        assert!(!requested(&mut cpu));
+    }
+}
@@ -0,0 +1,311 @@
+use std::{
+    io::{Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use super::serial::SerialDevice;
+
+// Messages are a kind byte followed by the data byte
+const TRANSFER: u8 = 0x01;
+const REPLY: u8 = 0x02;
+// The transfer we clocked timed out, the other end drops it
+const CANCEL: u8 = 0x03;
+
+// Bit times a transfer we clock waits for the other end to reply before it
+// completes with all 1 bits, like a real one with nothing armed on the
+// other end. Serial polls once per bit time, so this is emulated time:
+// 512 polls of 512 T-cycles at 8192 Hz, about 4 frames.
+const REPLY_TIMEOUT: u32 = 512;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum LinkMode {
+    // Wait for the other emulator on this address
+    Listen(String),
+    Connect(String),
+}
+
+// State shared with the thread reading from the socket
+#[derive(Default)]
+struct Link {
+    writer: Option<TcpStream>,
+    // Byte of a transfer the other end clocked, held until ours is armed
+    transfer: Option<u8>,
+    // Other end's byte for the transfer we clocked
+    reply: Option<u8>,
+}
+
+// Link cable to another emulator over TCP, exchanging bytes in lock-step
+// like the real cable. The end that clocks a transfer sends its byte and
+// waits for the reply. The other end holds that byte until its own Game
+// Boy armed a transfer on the external clock, then replies with its SB.
+// Neither end blocks its frame loop while it waits, Serial keeps polling.
+pub struct LinkCable {
+    link: Arc<Mutex<Link>>,
+    // Bit times our transfer has waited for its reply, None while we don't
+    // clock one
+    waited: Option<u32>,
+}
+
+impl LinkCable {
+    pub fn open(mode: &LinkMode) -> Result<Self, String> {
+        let link = Arc::new(Mutex::new(Link::default()));
+
+        let thread_link = Arc::clone(&link);
+        match mode {
+            LinkMode::Listen(address) => {
+                let listener =
+                    TcpListener::bind(address).map_err(|err| format!("{}: {}", address, err))?;
+                println!("Link cable waiting on {}", address);
+
+                thread::spawn(move || Self::listen(listener, thread_link));
+            }
+            LinkMode::Connect(address) => {
+                let stream =
+                    TcpStream::connect(address).map_err(|err| format!("{}: {}", address, err))?;
+                println!("Link cable connected to {}", address);
+
+                // Plugged in before the first transfer can go out
+                Self::connect_writer(&stream, &link).map_err(|err| err.to_string())?;
+                thread::spawn(move || Self::serve(stream, &thread_link));
+            }
+        }
+
+        Ok(Self { link, waited: None })
+    }
+
+    // One emulator at a time, the cable can be plugged in again once the
+    // last one went away
+    fn listen(listener: TcpListener, link: Arc<Mutex<Link>>) {
+        for stream in listener.incoming() {
+            // Our end of the cable is gone
+            if Arc::strong_count(&link) == 1 {
+                break;
+            }
+
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    println!("Link cable: {}", err);
+                    continue;
+                }
+            };
+
+            if let Ok(peer) = stream.peer_addr() {
+                println!("Link cable connected to {}", peer);
+            }
+
+            match Self::connect_writer(&stream, &link) {
+                Ok(()) => Self::serve(stream, &link),
+                Err(err) => println!("Link cable: {}", err),
+            }
+        }
+    }
+
+    fn connect_writer(stream: &TcpStream, link: &Mutex<Link>) -> std::io::Result<()> {
+        // Every transfer is a round trip, don't hold back the small writes
+        stream.set_nodelay(true)?;
+        link.lock().unwrap().writer = Some(stream.try_clone()?);
+
+        Ok(())
+    }
+
+    // Keeps what the other end sends for our Game Boy to pick up, until the
+    // other end goes away
+    fn serve(mut stream: TcpStream, link: &Mutex<Link>) {
+        let mut message = [0; 2];
+        while stream.read_exact(&mut message).is_ok() {
+            let mut link = link.lock().unwrap();
+
+            match message {
+                [TRANSFER, byte] => link.transfer = Some(byte),
+                [REPLY, byte] => link.reply = Some(byte),
+                [CANCEL, _] => link.transfer = None,
+                _ => (),
+            }
+        }
+
+        println!("Link cable disconnected");
+        *link.lock().unwrap() = Link::default();
+    }
+
+    fn send(link: &mut Link, kind: u8, byte: u8) -> bool {
+        match &mut link.writer {
+            Some(writer) => writer.write_all(&[kind, byte]).is_ok(),
+            None => false,
+        }
+    }
+}
+
+impl Drop for LinkCable {
+    // Unplugs the cable, the other end sees it disconnect
+    fn drop(&mut self) {
+        if let Some(writer) = &self.link.lock().unwrap().writer {
+            let _ = writer.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl SerialDevice for LinkCable {
+    fn exchange(&mut self, byte: u8, clocked: bool) -> Option<u8> {
+        let mut link = self.link.lock().unwrap();
+
+        if link.writer.is_none() {
+            // Unplugged, a transfer we clock reads all 1 bits
+            self.waited = None;
+            return clocked.then_some(0xFF);
+        }
+
+        if !clocked {
+            // Only taken once our byte made it to the other end
+            let transfer = link.transfer?;
+            if !Self::send(&mut link, REPLY, byte) {
+                return None;
+            }
+
+            link.transfer = None;
+            return Some(transfer);
+        }
+
+        // Both ends clocking at once, the other one reads all 1 bits
+        if link.transfer.take().is_some() {
+            Self::send(&mut link, REPLY, 0xFF);
+        }
+
+        let Some(waited) = self.waited else {
+            // Left over from a transfer that timed out
+            link.reply = None;
+
+            if !Self::send(&mut link, TRANSFER, byte) {
+                return Some(0xFF);
+            }
+
+            self.waited = Some(0);
+            return None;
+        };
+
+        if let Some(reply) = link.reply.take() {
+            self.waited = None;
+            return Some(reply);
+        }
+
+        if waited >= REPLY_TIMEOUT {
+            Self::send(&mut link, CANCEL, 0);
+            self.waited = None;
+            return Some(0xFF);
+        }
+
+        self.waited = Some(waited + 1);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{LinkCable, LinkMode, REPLY_TIMEOUT};
+    use crate::modules::serial::SerialDevice;
+
+    // Any free port on localhost
+    fn free_address() -> String {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+
+        format!("127.0.0.1:{}", port)
+    }
+
+    fn wait_until(condition: impl Fn() -> bool) {
+        let start = Instant::now();
+
+        while !condition() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    // Polls like Serial does until the other end's byte arrives
+    fn exchange(cable: &mut LinkCable, byte: u8, clocked: bool) -> u8 {
+        let start = Instant::now();
+
+        loop {
+            if let Some(received) = cable.exchange(byte, clocked) {
+                return received;
+            }
+
+            assert!(start.elapsed() < Duration::from_secs(5), "no byte arrived");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn plugged_in(cable: &LinkCable) -> bool {
+        cable.link.lock().unwrap().writer.is_some()
+    }
+
+    #[test]
+    fn exchanges_a_byte_in_each_direction_over_localhost() {
+        let address = free_address();
+        let mut listening = LinkCable::open(&LinkMode::Listen(address.clone())).unwrap();
+        let mut connected = LinkCable::open(&LinkMode::Connect(address)).unwrap();
+
+        // The connected end clocks, the listening end only replies once its
+        // own transfer is armed
+        assert_eq!(connected.exchange(0x12, true), None);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(connected.exchange(0x12, true), None);
+
+        assert_eq!(exchange(&mut listening, 0x34, false), 0x12);
+        assert_eq!(exchange(&mut connected, 0x12, true), 0x34);
+
+        // And the other way around
+        assert_eq!(listening.exchange(0x56, true), None);
+        assert_eq!(exchange(&mut connected, 0x78, false), 0x56);
+        assert_eq!(exchange(&mut listening, 0x56, true), 0x78);
+    }
+
+    #[test]
+    fn clocked_transfer_times_out_when_the_other_end_never_arms() {
+        let address = free_address();
+        let mut listening = LinkCable::open(&LinkMode::Listen(address.clone())).unwrap();
+        let mut connected = LinkCable::open(&LinkMode::Connect(address)).unwrap();
+
+        // Sending the byte, then waiting one poll per bit time
+        for _ in 0..=REPLY_TIMEOUT {
+            assert_eq!(connected.exchange(0x12, true), None);
+        }
+        assert_eq!(connected.exchange(0x12, true), Some(0xFF));
+
+        // The timed out transfer is dropped on the other end
+        wait_until(|| plugged_in(&listening));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(listening.exchange(0x34, false), None);
+    }
+
+    #[test]
+    fn listening_end_can_be_plugged_in_again() {
+        let address = free_address();
+        let mut listening = LinkCable::open(&LinkMode::Listen(address.clone())).unwrap();
+
+        let mut first = LinkCable::open(&LinkMode::Connect(address.clone())).unwrap();
+        assert_eq!(first.exchange(0x12, true), None);
+        assert_eq!(exchange(&mut listening, 0x34, false), 0x12);
+        assert_eq!(exchange(&mut first, 0x12, true), 0x34);
+
+        // Unplugged, a transfer we clock reads all 1 bits straight away
+        drop(first);
+        wait_until(|| !plugged_in(&listening));
+        assert_eq!(listening.exchange(0x56, true), Some(0xFF));
+
+        let mut second = LinkCable::open(&LinkMode::Connect(address)).unwrap();
+        assert_eq!(second.exchange(0x9A, true), None);
+        assert_eq!(exchange(&mut listening, 0xBC, false), 0x9A);
+        assert_eq!(exchange(&mut second, 0x9A, true), 0xBC);
+    }
+}
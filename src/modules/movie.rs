@@ -0,0 +1,230 @@
+use std::{fs, path::Path};
+
+use super::{
+    cpu::Cpu,
+    ram::Ram,
+    savestate::{StateReader, StateWriter},
+};
+
+const MAGIC: &[u8; 4] = b"GBMV";
+pub const MOVIE_VERSION: u16 = 1;
+
+// Joypad input for every frame from a known starting point: power-on, or a
+// save state embedded in the movie. As the core only depends on its own
+// state and this input, playing it back reproduces the run exactly, which
+// the hash taken at the end confirms.
+pub struct Movie {
+    pub start_state: Option<Vec<u8>>,
+    // Button mask (see joypad.rs) per frame
+    pub inputs: Vec<u8>,
+    // Machine hash after the last frame, 0 until the recording is finished
+    pub hash: u64,
+}
+
+impl Movie {
+    pub fn new(start_state: Option<Vec<u8>>) -> Self {
+        Self {
+            start_state,
+            inputs: Vec::new(),
+            hash: 0,
+        }
+    }
+
+    // FNV-1a over the framebuffer, WRAM and HRAM
+    pub fn machine_hash(cpu: &Cpu) -> u64 {
+        let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+        let mut add = |byte: u8| hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3);
+
+        for color in cpu.bus.ppu.video_buffer {
+            add(color.r);
+            add(color.g);
+            add(color.b);
+        }
+
+        for address in 0xC000..=0xDFFF {
+            add(Ram::wram_read(&cpu.bus.ram, address));
+        }
+
+        for address in 0xFF80..=0xFFFE {
+            add(Ram::hram_read(&cpu.bus.ram, address));
+        }
+
+        hash
+    }
+
+    // roms/game.gb -> roms/game.gbm
+    pub fn default_path(rom_path: &str) -> String {
+        Path::new(rom_path)
+            .with_extension("gbm")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    pub fn finish(&mut self, cpu: &Cpu) {
+        self.hash = Self::machine_hash(cpu);
+    }
+
+    pub fn save(&self, cpu: &Cpu, path: &str) -> Result<(), String> {
+        let mut movie = StateWriter::new();
+
+        movie.bytes(MAGIC);
+        movie.u16(MOVIE_VERSION);
+        movie.u32(cpu.bus.cart.rom_checksum());
+
+        match &self.start_state {
+            Some(state) => {
+                movie.bool(true);
+                movie.u32(state.len() as u32);
+                movie.bytes(state);
+            }
+            None => movie.bool(false),
+        }
+
+        movie.u32(self.inputs.len() as u32);
+        movie.bytes(&self.inputs);
+        movie.u64(self.hash);
+
+        fs::write(path, movie.into_vec()).map_err(|err| err.to_string())
+    }
+
+    pub fn load(cpu: &Cpu, path: &str) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|err| err.to_string())?;
+        let mut movie = StateReader::new(&data);
+
+        let mut magic = [0; 4];
+        movie
+            .bytes(&mut magic)
+            .map_err(|_| String::from("not a movie"))?;
+
+        if &magic != MAGIC {
+            return Err(String::from("not a movie"));
+        }
+
+        let version = movie.u16()?;
+        if version != MOVIE_VERSION {
+            return Err(format!(
+                "movie version {} is not supported (expected {})",
+                version, MOVIE_VERSION
+            ));
+        }
+
+        if movie.u32()? != cpu.bus.cart.rom_checksum() {
+            return Err(String::from("movie was recorded with a different ROM"));
+        }
+
+        let start_state = if movie.bool()? {
+            let mut state = vec![0; movie.u32()? as usize];
+            movie.bytes(&mut state)?;
+            Some(state)
+        } else {
+            None
+        };
+
+        let mut inputs = vec![0; movie.u32()? as usize];
+        movie.bytes(&mut inputs)?;
+
+        Ok(Self {
+            start_state,
+            inputs,
+            hash: movie.u64()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Movie;
+    use crate::modules::{
+        cpu::Cpu,
+        emu::Emu,
+        joypad::{Joypad, A, DOWN, LEFT, START},
+        options::Options,
+        pacer::CYCLES_PER_FRAME,
+        savestate::SaveState,
+    };
+
+    fn boot() -> Cpu {
+        let rom = format!("{}/roms/Tetris_World.gb", env!("CARGO_MANIFEST_DIR"));
+        let options = Options::parse(&[String::from("gameboy_emulator"), rom.clone()]);
+        Emu::boot(&options, &rom)
+    }
+
+    // One frame with the buttons held, like Emu::run_frame
+    fn frame(cpu: &mut Cpu, buttons: u8) {
+        Joypad::set_buttons(cpu, buttons);
+
+        let start = cpu.timer.ticks;
+        let frame = cpu.bus.ppu.current_frame;
+        while cpu.bus.ppu.current_frame == frame && cpu.timer.ticks - start < CYCLES_PER_FRAME {
+            assert!(cpu.step());
+        }
+    }
+
+    fn play(cpu: &mut Cpu, movie: &Movie) -> u64 {
+        if let Some(state) = &movie.start_state {
+            SaveState::restore(cpu, state).unwrap();
+        }
+
+        for &buttons in &movie.inputs {
+            frame(cpu, buttons);
+        }
+
+        Movie::machine_hash(cpu)
+    }
+
+    // A few buttons pressed in turn, over and over
+    fn inputs(frames: usize) -> Vec<u8> {
+        (0..frames)
+            .map(|i| match i % 40 {
+                0..=2 => START,
+                10..=12 => A,
+                20..=25 => LEFT,
+                30..=35 => DOWN,
+                _ => 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replaying_a_movie_reproduces_the_recorded_run() {
+        let mut cpu = boot();
+        let mut movie = Movie::new(None);
+        for buttons in inputs(100) {
+            frame(&mut cpu, buttons);
+            movie.inputs.push(buttons);
+        }
+        movie.finish(&cpu);
+
+        let path = std::env::temp_dir().join("movie-test.gbm");
+        let path = path.to_string_lossy();
+        movie.save(&cpu, &path).unwrap();
+
+        let mut replay = boot();
+        let loaded = Movie::load(&replay, &path).unwrap();
+        assert_eq!(play(&mut replay, &loaded), movie.hash);
+
+        // Tetris keeps the buttons it read in HRAM, which the hash covers
+        let mut other = boot();
+        let mut changed = Movie::new(None);
+        changed.inputs = inputs(100);
+        changed.inputs[90..].fill(A);
+        assert_ne!(play(&mut other, &changed), movie.hash);
+    }
+
+    #[test]
+    fn replaying_from_a_start_state_reproduces_the_recorded_run() {
+        let mut cpu = boot();
+        for buttons in inputs(30) {
+            frame(&mut cpu, buttons);
+        }
+
+        let mut movie = Movie::new(Some(SaveState::snapshot(&cpu)));
+        for buttons in inputs(60) {
+            frame(&mut cpu, buttons);
+            movie.inputs.push(buttons);
+        }
+        movie.finish(&cpu);
+
+        assert_eq!(play(&mut boot(), &movie), movie.hash);
+    }
+}
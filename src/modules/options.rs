@@ -0,0 +1,291 @@
+use super::{
+    emu::Speed,
+    link_cable::LinkMode,
+    pacer::Pacing,
+    ppu::Renderer,
+    trace::{TraceFormat, TraceOptions},
+};
+
+// Command line: <rom> [--renderer fifo|scanline]
+//               [--pacing realtime|audio|vsync|unthrottled]
+//               [--fast-forward <n>|uncapped] [--slow-motion <n>] [--rewind <MB>]
+//               [--record <movie>] [--play <movie>] [--verify <movie>]
+//               [--video <avi>]
+//               [--link-listen [<host>:]<port>] [--link-connect <host>:<port>]
+//               [--debug] [--bench <frames>]
+//               [--screenshot <frames>] [--screenshot-scale <n>] [--screenshot-path <file>]
+//               [--compare <png>] [--frames <n>] [--diff <file>] [--until-ld-bb]
+//               [--trace <file>] [--trace-format doctor|verbose]
+//               [--trace-pc <start>[-<end>]]... [--trace-bank <n>]...
+//               [--trace-start <n>] [--trace-stop <n>]
+//
+// or: disasm <rom> [--bank <n>] [--sym <file>]
+// or: test <rom>... [--timeout <seconds>]
+pub struct Options {
+    pub rom_path: String,
+    // Every ROM given, the test subcommand runs all of them
+    pub roms: Vec<String>,
+    pub renderer: Renderer,
+    pub pacing: Pacing,
+    // Speeds the fast-forward and slow motion hotkeys switch to
+    pub fast_forward: Speed,
+    pub slow_motion: Speed,
+    // Memory for rewind history in bytes, 0 disables it
+    pub rewind_budget: usize,
+    // Record input from power-on, or play a movie back in the window
+    pub record: Option<String>,
+    pub play: Option<String>,
+    // Play a movie headless and check its hash
+    pub verify: Option<String>,
+    // Record every frame into an AVI from power-on
+    pub video: Option<String>,
+    // Link cable to another instance, one listens and the other connects
+    pub link: Option<LinkMode>,
+    // Log executed instructions, see trace.rs
+    pub trace: TraceOptions,
+    // Start in the command-line debugger
+    pub debug: bool,
+    // Print a ROM bank as assembly instead of running, with names from the
+    // .sym file (by default the one next to the ROM, if any)
+    pub disassemble: bool,
+    pub bank: u16,
+    pub symbols: Option<String>,
+    // Run test ROMs headless and report their verdicts, giving each this
+    // many emulated seconds
+    pub test: bool,
+    pub timeout: u32,
+    // Run headless and unthrottled for this many frames and report timing
+    pub bench: Option<u32>,
+    // Run headless for this many frames and save the screen as a PNG, named
+    // after the ROM and the time unless a path is given
+    pub screenshot: Option<u32>,
+    pub screenshot_scale: u32,
+    pub screenshot_path: Option<String>,
+    // Run headless for this many frames and compare the screen against a
+    // reference PNG, writing the differences to a diff image
+    pub compare: Option<String>,
+    pub frames: u32,
+    pub diff_path: Option<String>,
+    // Stop running headless early when the ROM executes LD B,B
+    pub until_breakpoint: bool,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Self {
+        let mut options = Self {
+            rom_path: String::new(),
+            roms: Vec::new(),
+            renderer: Renderer::Fifo,
+            pacing: Pacing::Realtime,
+            fast_forward: Speed::Fast(4),
+            slow_motion: Speed::Slow(2),
+            rewind_budget: 64 << 20,
+            record: None,
+            play: None,
+            verify: None,
+            video: None,
+            link: None,
+            trace: TraceOptions::new(),
+            debug: false,
+            disassemble: false,
+            bank: 0,
+            symbols: None,
+            test: false,
+            timeout: 120,
+            bench: None,
+            screenshot: None,
+            screenshot_scale: 1,
+            screenshot_path: None,
+            compare: None,
+            frames: 120,
+            diff_path: None,
+            until_breakpoint: false,
+        };
+
+        let mut args = args.iter().skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--renderer" => {
+                    options.renderer = match args.next().map(String::as_str) {
+                        Some("fifo") => Renderer::Fifo,
+                        Some("scanline") => Renderer::Scanline,
+                        other => panic!("Unknown renderer: {:?}", other),
+                    }
+                }
+                "--pacing" => {
+                    options.pacing = match args.next().map(String::as_str) {
+                        Some("realtime") => Pacing::Realtime,
+                        Some("audio") => Pacing::Audio,
+                        Some("vsync") => Pacing::Vsync,
+                        Some("unthrottled") => Pacing::Unthrottled,
+                        other => panic!("Unknown pacing: {:?}", other),
+                    }
+                }
+                "--fast-forward" => {
+                    options.fast_forward = match args.next().map(String::as_str) {
+                        Some("uncapped") => Speed::Uncapped,
+                        other => Speed::Fast(Self::multiplier("--fast-forward", other)),
+                    }
+                }
+                "--slow-motion" => {
+                    let divisor =
+                        Self::multiplier("--slow-motion", args.next().map(String::as_str));
+                    options.slow_motion = Speed::Slow(divisor);
+                }
+                "--rewind" => {
+                    let megabytes: usize = args
+                        .next()
+                        .and_then(|megabytes| megabytes.parse().ok())
+                        .expect("--rewind needs a size in MB");
+                    options.rewind_budget = megabytes << 20;
+                }
+                "--record" => options.record = Some(Self::path("--record", args.next())),
+                "--play" => options.play = Some(Self::path("--play", args.next())),
+                "--verify" => options.verify = Some(Self::path("--verify", args.next())),
+                "--video" => options.video = Some(Self::path("--video", args.next())),
+                "--link-listen" => {
+                    let address = args.next().cloned().expect("--link-listen needs a port");
+                    // Only this machine can connect unless a host is given
+                    let address = if address.contains(':') {
+                        address
+                    } else {
+                        format!("127.0.0.1:{}", address)
+                    };
+                    options.link = Some(LinkMode::Listen(address));
+                }
+                "--link-connect" => {
+                    let address = args.next().cloned();
+                    options.link = Some(LinkMode::Connect(
+                        address.expect("--link-connect needs a host:port address"),
+                    ));
+                }
+                "--trace" => options.trace.path = Some(Self::path("--trace", args.next())),
+                "--trace-format" => {
+                    options.trace.format = match args.next().map(String::as_str) {
+                        Some("doctor") => TraceFormat::Doctor,
+                        Some("verbose") => TraceFormat::Verbose,
+                        other => panic!("Unknown trace format: {:?}", other),
+                    }
+                }
+                "--trace-pc" => {
+                    let range = args.next().map(String::as_str).unwrap_or("");
+                    let (start, end) = range.split_once('-').unwrap_or((range, range));
+                    options
+                        .trace
+                        .ranges
+                        .push((Self::hex("--trace-pc", start), Self::hex("--trace-pc", end)));
+                }
+                "--trace-bank" => {
+                    let bank = args.next().and_then(|bank| bank.parse().ok());
+                    options
+                        .trace
+                        .banks
+                        .push(bank.expect("--trace-bank needs a bank number"));
+                }
+                "--trace-start" => options.trace.start = Self::count("--trace-start", args.next()),
+                "--trace-stop" => {
+                    options.trace.stop = Some(Self::count("--trace-stop", args.next()))
+                }
+                "--debug" => options.debug = true,
+                "disasm" if options.rom_path.is_empty() => options.disassemble = true,
+                "test" if options.rom_path.is_empty() => options.test = true,
+                "--timeout" => {
+                    let seconds = args.next().and_then(|seconds| seconds.parse().ok());
+                    options.timeout = seconds.expect("--timeout needs a number of seconds");
+                }
+                "--bank" => {
+                    let bank = args.next().and_then(|bank| bank.parse().ok());
+                    options.bank = bank.expect("--bank needs a bank number");
+                }
+                "--sym" => options.symbols = Some(Self::path("--sym", args.next())),
+                "--bench" => {
+                    let frames = args.next().and_then(|frames| frames.parse().ok());
+                    options.bench = Some(frames.expect("--bench needs a frame count"));
+                }
+                "--screenshot" => {
+                    let frames = args.next().and_then(|frames| frames.parse().ok());
+                    options.screenshot = Some(frames.expect("--screenshot needs a frame count"));
+                }
+                "--screenshot-scale" => {
+                    options.screenshot_scale =
+                        Self::multiplier("--screenshot-scale", args.next().map(String::as_str));
+                }
+                "--screenshot-path" => {
+                    options.screenshot_path = Some(Self::path("--screenshot-path", args.next()))
+                }
+                "--compare" => options.compare = Some(Self::path("--compare", args.next())),
+                "--frames" => {
+                    let frames = args.next().and_then(|frames| frames.parse().ok());
+                    options.frames = frames.expect("--frames needs a frame count");
+                }
+                "--diff" => options.diff_path = Some(Self::path("--diff", args.next())),
+                "--until-ld-bb" => options.until_breakpoint = true,
+                _ => {
+                    options.rom_path = arg.clone();
+                    options.roms.push(arg.clone());
+                }
+            }
+        }
+
+        if options.rom_path.is_empty() {
+            panic!("No ROM path provided!")
+        }
+
+        options
+    }
+
+    fn path(option: &str, value: Option<&String>) -> String {
+        value
+            .cloned()
+            .unwrap_or_else(|| panic!("{} needs a file path", option))
+    }
+
+    // Hex address, with or without a $ or 0x prefix
+    fn hex(option: &str, value: &str) -> u16 {
+        let digits = value.trim_start_matches('$').trim_start_matches("0x");
+
+        u16::from_str_radix(digits, 16)
+            .unwrap_or_else(|_| panic!("{} needs hex addresses, got {:?}", option, value))
+    }
+
+    fn count(option: &str, value: Option<&String>) -> u64 {
+        value
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_else(|| panic!("{} needs an instruction count", option))
+    }
+
+    fn multiplier(option: &str, value: Option<&str>) -> u32 {
+        match value.and_then(|value| value.parse().ok()) {
+            Some(multiplier) if multiplier >= 1 => multiplier,
+            _ => panic!(
+                "{} needs a multiplier of at least 1, got {:?}",
+                option, value
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Options;
+
+    fn parse(args: &[&str]) -> Options {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Options::parse(&args)
+    }
+
+    #[test]
+    fn only_the_disasm_subcommand_disassembles() {
+        let options = parse(&["gameboy_emulator", "game.gb", "--bank", "3"]);
+        assert!(!options.disassemble);
+
+        let options = parse(&["gameboy_emulator", "disasm", "game.gb", "--bank", "3"]);
+        assert!(options.disassemble);
+        assert_eq!(options.bank, 3);
+        assert_eq!(options.rom_path, "game.gb");
+
+        let options = parse(&["gameboy_emulator", "disasm", "game.gb"]);
+        assert_eq!(options.bank, 0);
+    }
+}
@@ -0,0 +1,141 @@
+use std::time::{Duration, Instant};
+
+use sdl2::{
+    audio::{AudioQueue, AudioSpecDesired},
+    Sdl,
+};
+
+pub const CPU_FREQUENCY: u64 = 4_194_304;
+// 154 lines of 456 dots, which makes the DMG run at ~59.73 Hz
+pub const CYCLES_PER_FRAME: u64 = 70_224;
+
+pub const SAMPLE_RATE: i32 = 48_000;
+pub const CHANNELS: u8 = 2;
+// Frames of audio kept queued ahead of the device when pacing on audio
+const AUDIO_LATENCY: u32 = 3;
+// How far real-time pacing may fall behind before it stops trying to catch up
+const MAX_LAG: u32 = 4;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Pacing {
+    // Sleep until the next frame is due
+    Realtime,
+    // Block on the audio device draining its queue
+    Audio,
+    // Block on the display's vertical blank when presenting
+    Vsync,
+    Unthrottled,
+}
+
+// Keeps the frontend running at the Game Boy's frame rate, the emulation
+// core itself never looks at the wall clock.
+pub struct Pacer {
+    pub pacing: Pacing,
+    frame_period: Duration,
+    next_frame: Instant,
+    audio: Option<AudioQueue<i16>>,
+    // Fractional samples carried over to the next frame, in CPU cycles
+    sample_remainder: u64,
+}
+
+impl Pacer {
+    pub fn new(pacing: Pacing, sdl_context: &Sdl) -> Self {
+        let mut pacer = Self {
+            pacing,
+            frame_period: Self::frame_period(),
+            next_frame: Instant::now(),
+            audio: None,
+            sample_remainder: 0,
+        };
+
+        if pacing == Pacing::Audio {
+            match Self::open_audio(sdl_context) {
+                Ok(queue) => pacer.audio = Some(queue),
+                Err(err) => {
+                    println!("Audio pacing unavailable ({}), using real-time", err);
+                    pacer.pacing = Pacing::Realtime;
+                }
+            }
+        }
+
+        pacer
+    }
+
+    pub fn frame_period() -> Duration {
+        Duration::from_nanos(CYCLES_PER_FRAME * 1_000_000_000 / CPU_FREQUENCY)
+    }
+
+    // Waits for the given number of frame periods, called once per loop of
+    // the frontend (more than one period is how slow motion holds a frame).
+    pub fn wait(&mut self, frames: u32) {
+        match self.pacing {
+            Pacing::Realtime => self.wait_realtime(frames),
+            Pacing::Audio => self.wait_audio(frames),
+            // Canvas::present already blocked for one period on vsync
+            Pacing::Vsync if frames > 1 => {
+                std::thread::sleep(self.frame_period * (frames - 1));
+            }
+            Pacing::Vsync | Pacing::Unthrottled => {}
+        }
+    }
+
+    fn wait_realtime(&mut self, frames: u32) {
+        let now = Instant::now();
+
+        if now < self.next_frame {
+            std::thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > self.frame_period * MAX_LAG {
+            // Too slow (or we were stopped in a debugger), drop the debt
+            // instead of running fast until it is paid back.
+            self.next_frame = now;
+        }
+
+        self.next_frame += self.frame_period * frames;
+    }
+
+    fn open_audio(sdl_context: &Sdl) -> Result<AudioQueue<i16>, String> {
+        let audio_subsystem = sdl_context.audio()?;
+
+        let spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(CHANNELS),
+            samples: None,
+        };
+
+        let queue = audio_subsystem.open_queue::<i16, _>(None, &spec)?;
+        queue.resume();
+
+        Ok(queue)
+    }
+
+    // There is no APU yet, so silence is queued for the frames waited on and
+    // the device consuming it at its own rate sets the pace.
+    fn wait_audio(&mut self, frames: u32) {
+        let queue = match &self.audio {
+            Some(queue) => queue,
+            None => return,
+        };
+
+        let freq = queue.spec().freq as u64;
+        let channels = queue.spec().channels as u64;
+
+        let cycles = self.sample_remainder + freq * CYCLES_PER_FRAME * frames as u64;
+        let samples = cycles / CPU_FREQUENCY;
+        self.sample_remainder = cycles % CPU_FREQUENCY;
+
+        let silence = vec![0i16; (samples * channels) as usize];
+        if let Err(err) = queue.queue_audio(&silence) {
+            println!("Audio pacing failed ({}), using real-time", err);
+            self.audio = None;
+            self.pacing = Pacing::Realtime;
+            self.next_frame = Instant::now();
+            return;
+        }
+
+        let frame_bytes = (freq * CYCLES_PER_FRAME / CPU_FREQUENCY * channels * 2) as u32;
+
+        while queue.size() > frame_bytes * AUDIO_LATENCY {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
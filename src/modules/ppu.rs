@@ -1,15 +1,13 @@
-use std::{cell::RefCell, rc::Rc};
-
 use sdl2::pixels::Color;
 
 use crate::enums::interrupt_types::InterruptType;
 
 use super::{
-    bus::Bus,
     cpu::Cpu,
-    emu::Emu,
     interrupts::interrupt,
-    lcd::{LCDMode, Lcd, StatSrc},
+    lcd::{LCDMode, Lcd},
+    savestate::{StateReader, StateWriter},
+    scanline::Scanline,
 };
 
 const LINES_PER_FRAME: u32 = 154;
@@ -26,56 +24,202 @@ const TILE_COLORS: [Color; 4] = [
     Color::RGB(0, 0, 0),
 ];
 
-pub struct FiFoEntry {
-    next: Option<Rc<RefCell<FiFoEntry>>>,
-    value: Color, // color value
+#[derive(Clone, Copy, Default)]
+pub struct FiFoPixel {
+    pub color: u8,   // 2-bit color index
+    pub palette: u8, // OBP0/OBP1 for sprite pixels
+    pub bg_priority: bool,
 }
 
+// Neither FIFO ever holds more than one tile row: the fetcher only pushes
+// into an empty background FIFO and sprites are mixed into 8 slots.
+const FIFO_SIZE: usize = 8;
+
 pub struct FiFo {
-    head: Option<Rc<RefCell<FiFoEntry>>>,
-    tail: Option<Rc<RefCell<FiFoEntry>>>, // or *mut FiFoEntry, will see later which is better
-    size: u32,
+    pixels: [FiFoPixel; FIFO_SIZE],
+    head: usize,
+    size: usize,
 }
 
 impl FiFo {
     pub fn new() -> Self {
         Self {
-            head: None,
-            tail: None,
+            pixels: [FiFoPixel::default(); FIFO_SIZE],
+            head: 0,
             size: 0,
         }
     }
+
+    fn push(&mut self, value: FiFoPixel) {
+        if self.size == FIFO_SIZE {
+            panic!("FIFO overflow");
+        }
+
+        self.pixels[(self.head + self.size) % FIFO_SIZE] = value;
+        self.size += 1;
+    }
+
+    fn pop(&mut self) -> FiFoPixel {
+        if self.size == 0 {
+            panic!("FIFO underflow");
+        }
+
+        let val = self.pixels[self.head];
+        self.head = (self.head + 1) % FIFO_SIZE;
+
+        self.size -= 1;
+        val
+    }
+
+    // Sprite pixels are mixed into the sprite FIFO in place
+    fn entry(&mut self, index: usize) -> &mut FiFoPixel {
+        &mut self.pixels[(self.head + index) % FIFO_SIZE]
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.size = 0;
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        for pixel in self.pixels {
+            state.bytes(&[pixel.color, pixel.palette, pixel.bg_priority as u8]);
+        }
+        state.u8(self.head as u8);
+        state.u8(self.size as u8);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        for pixel in self.pixels.iter_mut() {
+            pixel.color = state.u8()?;
+            pixel.palette = state.u8()?;
+            pixel.bg_priority = state.bool()?;
+        }
+        self.head = state.u8()? as usize % FIFO_SIZE;
+        self.size = (state.u8()? as usize).min(FIFO_SIZE);
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct OamEntry {
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub flags: u8,
 }
 
 pub struct PixelFiFo {
     current_fetch_state: FetchState,
-    fifo: FiFo,
-    line_x: u8,
+    fetch_ticks: u8,
+    bg_fifo: FiFo,
+    obj_fifo: FiFo,
     pushed_x: u8,
     fetch_x: u8,
     bgw_fetch_data: [u8; 3],
-    fetch_entry_data: [u8; 6],
-    map_y: u8,
-    map_x: u8,
-    tile_y: u8,
-    fifo_x: u8,
+
+    // Pixels shifted out but not drawn at the start of the line: SCX % 8,
+    // or the part of the window left of the screen when WX < 7.
+    discard: u8,
+    // The first tile fetched on each line is thrown away
+    first_fetch: bool,
+    pub window_active: bool,
+
+    // Up to 10 sprites found by the OAM scan for the current line
+    pub line_sprites: [OamEntry; 10],
+    pub sprite_count: usize,
+    sprites_fetched: u16,
+    sprite_fetch: usize,
+    sprite_fetch_ticks: u8,
+    // Background/window tile that last paid the extra sprite fetch delay
+    penalty_tile: Option<i16>,
 }
 
 impl PixelFiFo {
     pub fn new() -> Self {
         Self {
             current_fetch_state: FetchState::Tile,
-            fifo: FiFo::new(),
-            line_x: 0,
+            fetch_ticks: 0,
+            bg_fifo: FiFo::new(),
+            obj_fifo: FiFo::new(),
             pushed_x: 0,
             fetch_x: 0,
             bgw_fetch_data: [0; 3],
-            fetch_entry_data: [0; 6],
-            map_y: 0,
-            map_x: 0,
-            tile_y: 0,
-            fifo_x: 0,
+            discard: 0,
+            first_fetch: false,
+            window_active: false,
+            line_sprites: [OamEntry::default(); 10],
+            sprite_count: 0,
+            sprites_fetched: 0,
+            sprite_fetch: 0,
+            sprite_fetch_ticks: 0,
+            penalty_tile: None,
+        }
+    }
+
+    // A pixel is shifted out this dot, either from the FIFO or from the tile
+    // the fetcher is about to push into it.
+    fn pixel_ready(&self) -> bool {
+        self.bg_fifo.size > 0 || matches!(self.current_fetch_state, FetchState::Push)
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.current_fetch_state as u8);
+        state.u8(self.fetch_ticks);
+        self.bg_fifo.save_state(state);
+        self.obj_fifo.save_state(state);
+        state.bytes(&[self.pushed_x, self.fetch_x]);
+        state.bytes(&self.bgw_fetch_data);
+        state.u8(self.discard);
+        state.bool(self.first_fetch);
+        state.bool(self.window_active);
+
+        for sprite in self.line_sprites {
+            state.bytes(&[sprite.y, sprite.x, sprite.tile, sprite.flags]);
         }
+        state.u8(self.sprite_count as u8);
+        state.u16(self.sprites_fetched);
+        state.u8(self.sprite_fetch as u8);
+        state.u8(self.sprite_fetch_ticks);
+
+        // i16::MIN for no tile, penalty tiles are never negative
+        state.u16(self.penalty_tile.unwrap_or(i16::MIN) as u16);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.current_fetch_state = match state.u8()? {
+            0 => FetchState::Tile,
+            1 => FetchState::Data0,
+            2 => FetchState::Data1,
+            3 => FetchState::Push,
+            other => return Err(format!("invalid fetcher state {}", other)),
+        };
+        self.fetch_ticks = state.u8()?;
+        self.bg_fifo.load_state(state)?;
+        self.obj_fifo.load_state(state)?;
+        self.pushed_x = state.u8()?;
+        self.fetch_x = state.u8()?;
+        state.bytes(&mut self.bgw_fetch_data)?;
+        self.discard = state.u8()?;
+        self.first_fetch = state.bool()?;
+        self.window_active = state.bool()?;
+
+        for sprite in self.line_sprites.iter_mut() {
+            sprite.y = state.u8()?;
+            sprite.x = state.u8()?;
+            sprite.tile = state.u8()?;
+            sprite.flags = state.u8()?;
+        }
+        self.sprite_count = (state.u8()? as usize).min(10);
+        self.sprites_fetched = state.u16()?;
+        self.sprite_fetch = state.u8()? as usize;
+        self.sprite_fetch_ticks = state.u8()?;
+
+        let penalty_tile = state.u16()? as i16;
+        self.penalty_tile = (penalty_tile != i16::MIN).then_some(penalty_tile);
+
+        Ok(())
     }
 }
 
@@ -85,14 +229,26 @@ pub struct Ppu {
 
     pub current_frame: u32,
     pub line_ticks: u32,
+    next_event: u32,
     pub video_buffer: [Color; (XRES * YRES) as usize],
 
-    pub target_frame_time: u64,
-    pub prev_frame_time: u64,
-    pub start_time: u64,
-    pub frame_count: u64,
-
     pub pfc: PixelFiFo,
+
+    // Internal line counter of the window, only advances on lines where the
+    // window was actually drawn.
+    pub window_line: u8,
+    // LY matched WY at the start of a line earlier in this frame
+    pub window_triggered: bool,
+
+    pub renderer: Renderer,
+    // Mode 3 length of the current line when it isn't drawn dot by dot
+    xfer_length: u32,
+
+    // Screen shows nothing: LCD is off, or this is the first frame after
+    // turning it back on, which real hardware doesn't display either.
+    pub blank: bool,
+    skip_frame: bool,
+    first_line: bool,
 }
 
 impl Ppu {
@@ -103,20 +259,27 @@ impl Ppu {
 
             current_frame: 0,
             line_ticks: 0,
+            next_event: 0,
             video_buffer: [TILE_COLORS[0]; (XRES * YRES) as usize],
 
-            target_frame_time: 1000 / 60,
-            prev_frame_time: 0,
-            start_time: 0,
-            frame_count: 0,
-
             pfc: PixelFiFo::new(),
+
+            window_line: 0,
+            window_triggered: false,
+
+            renderer: Renderer::Fifo,
+            xfer_length: 0,
+
+            blank: false,
+            skip_frame: false,
+            first_line: false,
         }
     }
 
     pub fn init(cpu: &mut Cpu) {
         cpu.bus.ppu.current_frame = 0;
         cpu.bus.ppu.line_ticks = 0;
+        cpu.bus.ppu.next_event = 0;
         cpu.bus.ppu.video_buffer = [TILE_COLORS[0]; (XRES * YRES) as usize];
 
         Lcd::init(&mut cpu.bus.lcd);
@@ -126,14 +289,41 @@ impl Ppu {
     }
 
     pub fn tick(cpu: &mut Cpu) {
+        if cpu.bus.lcd.lcd_enabled() == 0 {
+            return;
+        }
+
         cpu.bus.ppu.line_ticks += 1;
 
+        if cpu.bus.ppu.line_ticks < cpu.bus.ppu.next_event {
+            return;
+        }
+
         match cpu.bus.lcd.get_lcds_mode() {
             LCDMode::Oam => Self::mode_oam(cpu),
             LCDMode::Xfer => Self::mode_xfer(cpu),
             LCDMode::Vblank => Self::mode_vblank(cpu),
             LCDMode::Hblank => Self::mode_hblank(cpu),
         }
+
+        cpu.bus.ppu.next_event = Self::next_event(cpu);
+    }
+
+    // First dot of the line on which the current mode has anything to do,
+    // every dot in between only advances line_ticks.
+    fn next_event(cpu: &Cpu) -> u32 {
+        let ppu = &cpu.bus.ppu;
+
+        match cpu.bus.lcd.get_lcds_mode() {
+            LCDMode::Oam => 80,
+            LCDMode::Xfer => match ppu.renderer {
+                Renderer::Fifo => ppu.line_ticks + 1,
+                Renderer::Scanline => 80 + ppu.xfer_length,
+            },
+            LCDMode::Hblank if ppu.first_line => 80,
+            LCDMode::Vblank if cpu.bus.lcd.ly == (LINES_PER_FRAME - 1) as u8 => 4,
+            _ => TICKS_PER_LINE,
+        }
     }
 
     pub fn oam_write(&mut self, address: u16, value: u8) {
@@ -162,44 +352,166 @@ impl Ppu {
         self.vram[(address - 0x8000) as usize]
     }
 
+    // The renderer is a frontend setting and stays as it is
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.oam_ram);
+        state.bytes(&self.vram);
+
+        state.u32(self.current_frame);
+        state.u32(self.line_ticks);
+        state.u32(self.next_event);
+
+        for color in self.video_buffer {
+            state.bytes(&[color.r, color.g, color.b]);
+        }
+
+        self.pfc.save_state(state);
+
+        state.u8(self.window_line);
+        state.bool(self.window_triggered);
+        state.u32(self.xfer_length);
+        state.bool(self.blank);
+        state.bool(self.skip_frame);
+        state.bool(self.first_line);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.oam_ram)?;
+        state.bytes(&mut self.vram)?;
+
+        self.current_frame = state.u32()?;
+        self.line_ticks = state.u32()?;
+        self.next_event = state.u32()?;
+
+        for color in self.video_buffer.iter_mut() {
+            let mut rgb = [0; 3];
+            state.bytes(&mut rgb)?;
+            *color = Color::RGB(rgb[0], rgb[1], rgb[2]);
+        }
+
+        self.pfc.load_state(state)?;
+
+        self.window_line = state.u8()?;
+        self.window_triggered = state.bool()?;
+        self.xfer_length = state.u32()?;
+        self.blank = state.bool()?;
+        self.skip_frame = state.bool()?;
+        self.first_line = state.bool()?;
+
+        Ok(())
+    }
+
+    // LCDC bit 7 cleared: LY and the mode drop to 0 and the PPU stops.
+    pub fn lcd_off(cpu: &mut Cpu) {
+        Self::pipeline_fifo_reset(cpu);
+
+        cpu.bus.ppu.line_ticks = 0;
+        cpu.bus.ppu.blank = true;
+        cpu.bus.ppu.first_line = false;
+
+        cpu.bus.lcd.ly = 0;
+        cpu.bus.lcd.set_lcds_mode(LCDMode::Hblank);
+        cpu.bus.lcd.reset_stat_line();
+    }
+
+    // LCDC bit 7 set: line 0 restarts, but without an OAM scan (STAT reports
+    // mode 0 for those dots) and the first frame is not shown.
+    pub fn lcd_on(cpu: &mut Cpu) {
+        cpu.bus.ppu.line_ticks = 0;
+        cpu.bus.ppu.skip_frame = true;
+        cpu.bus.ppu.first_line = true;
+        cpu.bus.ppu.window_line = 0;
+        cpu.bus.ppu.window_triggered = false;
+
+        cpu.bus.lcd.ly = 0;
+        cpu.bus.lcd.set_lcds_mode(LCDMode::Hblank);
+        Lcd::update_stat(cpu);
+
+        cpu.bus.ppu.next_event = Self::next_event(cpu);
+    }
+
     fn mode_oam(cpu: &mut Cpu) {
         if cpu.bus.ppu.line_ticks >= 80 {
-            cpu.bus.lcd.set_lcds_mode(LCDMode::Xfer);
+            Self::oam_scan(cpu);
+            Self::start_xfer(cpu);
+        }
+    }
+
+    fn start_xfer(cpu: &mut Cpu) {
+        cpu.bus.lcd.set_lcds_mode(LCDMode::Xfer);
+        Lcd::update_stat(cpu);
+
+        if cpu.bus.lcd.ly == cpu.bus.lcd.wy {
+            cpu.bus.ppu.window_triggered = true;
+        }
 
-            cpu.bus.ppu.pfc.current_fetch_state = FetchState::Tile;
-            cpu.bus.ppu.pfc.line_x = 0;
-            cpu.bus.ppu.pfc.fetch_x = 0;
-            cpu.bus.ppu.pfc.pushed_x = 0;
-            cpu.bus.ppu.pfc.fifo_x = 0;
+        let pfc = &mut cpu.bus.ppu.pfc;
+        pfc.current_fetch_state = FetchState::Tile;
+        pfc.fetch_ticks = 0;
+        pfc.fetch_x = 0;
+        pfc.pushed_x = 0;
+        pfc.discard = cpu.bus.lcd.scx % 8;
+        pfc.first_fetch = true;
+        pfc.window_active = false;
+        pfc.sprites_fetched = 0;
+        pfc.penalty_tile = None;
+
+        if cpu.bus.ppu.renderer == Renderer::Scanline {
+            cpu.bus.ppu.xfer_length = Scanline::xfer_length(cpu);
         }
     }
 
     fn mode_xfer(cpu: &mut Cpu) {
-        Self::pipeline_process(cpu);
-        if cpu.bus.ppu.pfc.pushed_x >= XRES as u8 {
-            Self::pipeline_fifo_reset(cpu);
+        let done = match cpu.bus.ppu.renderer {
+            Renderer::Fifo => {
+                Self::pipeline_process(cpu);
+                cpu.bus.ppu.pfc.pushed_x >= XRES as u8
+            }
+            Renderer::Scanline => cpu.bus.ppu.line_ticks >= 80 + cpu.bus.ppu.xfer_length,
+        };
 
-            cpu.bus.lcd.set_lcds_mode(LCDMode::Hblank);
+        if done {
+            if cpu.bus.ppu.renderer == Renderer::Scanline {
+                Scanline::render_line(cpu);
+            }
+
+            Self::pipeline_fifo_reset(cpu);
 
-            if cpu.bus.lcd.stat_interrupt(StatSrc::Hblank) != 0 {
-                interrupt::request(cpu, InterruptType::LcdStat);
+            if cpu.bus.ppu.pfc.window_active {
+                cpu.bus.ppu.window_line += 1;
             }
+
+            cpu.bus.lcd.set_lcds_mode(LCDMode::Hblank);
+            Lcd::update_stat(cpu);
         }
     }
 
     fn mode_vblank(cpu: &mut Cpu) {
-        if cpu.bus.ppu.line_ticks >= TICKS_PER_LINE {
-            Lcd::increment_ly(cpu);
+        // On line 153 LY already reads 0 after the first M-cycle, so LYC=0
+        // matches a line early.
+        if cpu.bus.lcd.ly == (LINES_PER_FRAME - 1) as u8 && cpu.bus.ppu.line_ticks == 4 {
+            cpu.bus.lcd.ly = 0;
+            Lcd::update_stat(cpu);
+        }
 
-            if cpu.bus.lcd.ly >= (LINES_PER_FRAME as u8) {
+        if cpu.bus.ppu.line_ticks >= TICKS_PER_LINE {
+            if cpu.bus.lcd.ly == 0 {
                 cpu.bus.lcd.set_lcds_mode(LCDMode::Oam);
-                cpu.bus.lcd.ly = 0;
+            } else {
+                Lcd::increment_ly(cpu);
             }
 
+            Lcd::update_stat(cpu);
             cpu.bus.ppu.line_ticks = 0;
         }
     }
     fn mode_hblank(cpu: &mut Cpu) {
+        if cpu.bus.ppu.first_line && cpu.bus.ppu.line_ticks >= 80 {
+            cpu.bus.ppu.first_line = false;
+            Self::start_xfer(cpu);
+            return;
+        }
+
         if cpu.bus.ppu.line_ticks >= TICKS_PER_LINE {
             Lcd::increment_ly(cpu);
 
@@ -207,136 +519,153 @@ impl Ppu {
                 cpu.bus.lcd.set_lcds_mode(LCDMode::Vblank);
 
                 interrupt::request(cpu, InterruptType::Vblank);
-
-                if cpu.bus.lcd.stat_interrupt(StatSrc::Vblank) != 0 {
-                    interrupt::request(cpu, InterruptType::LcdStat);
-                }
+                Lcd::update_stat(cpu);
 
                 cpu.bus.ppu.current_frame += 1;
+                cpu.bus.ppu.window_line = 0;
+                cpu.bus.ppu.window_triggered = false;
 
-                // calc FPS
-                let end = Emu::get_ticks();
-                let frame_time = end - cpu.bus.ppu.prev_frame_time;
-
-                if frame_time < cpu.bus.ppu.target_frame_time {
-                    Emu::delay(cpu.bus.ppu.target_frame_time - frame_time);
-                }
-
-                if end - cpu.bus.ppu.start_time >= 1000 {
-                    // let fps = cpu.bus.ppu.frame_count;
-                    cpu.bus.ppu.start_time = end;
-                    cpu.bus.ppu.frame_count = 0;
-
-                    // println!("FPS: {}", fps);
+                if cpu.bus.ppu.skip_frame {
+                    cpu.bus.ppu.skip_frame = false;
+                } else {
+                    cpu.bus.ppu.blank = false;
                 }
-
-                cpu.bus.ppu.frame_count += 1;
-                cpu.bus.ppu.prev_frame_time = Emu::get_ticks();
             } else {
                 cpu.bus.lcd.set_lcds_mode(LCDMode::Oam);
+                Lcd::update_stat(cpu);
             }
 
             cpu.bus.ppu.line_ticks = 0;
         }
     }
 
-    fn pixel_fifo_push(cpu: &mut Cpu, value: Color) {
-        let next = Rc::new(RefCell::new(FiFoEntry { next: None, value }));
+    // Mode 2: pick the first 10 sprites (in OAM order) covering this line
+    pub fn oam_scan(cpu: &mut Cpu) {
+        let ppu = &mut cpu.bus.ppu;
+        let line = cpu.bus.lcd.ly as u16 + 16;
+        let height = cpu.bus.lcd.obj_height() as u16;
 
-        if cpu.bus.ppu.pfc.fifo.head.is_none() {
-            cpu.bus.ppu.pfc.fifo.head = Some(next.clone());
-            cpu.bus.ppu.pfc.fifo.tail = Some(next.clone());
-        } else {
-            cpu.bus
-                .ppu
-                .pfc
-                .fifo
-                .tail
-                .as_ref()
-                .unwrap()
-                .borrow_mut()
-                .next = Some(next.clone());
-            cpu.bus.ppu.pfc.fifo.tail = Some(next.clone());
-        }
+        ppu.pfc.sprite_count = 0;
+
+        for entry in ppu.oam_ram.chunks_exact(4) {
+            if ppu.pfc.sprite_count == ppu.pfc.line_sprites.len() {
+                break;
+            }
 
-        cpu.bus.ppu.pfc.fifo.size += 1;
+            let y = entry[0] as u16;
+            if line >= y && line < y + height {
+                ppu.pfc.line_sprites[ppu.pfc.sprite_count] = OamEntry {
+                    y: entry[0],
+                    x: entry[1],
+                    tile: entry[2],
+                    flags: entry[3],
+                };
+                ppu.pfc.sprite_count += 1;
+            }
+        }
     }
 
-    fn pixel_fifo_pop(cpu: &mut Cpu) -> Color {
-        if cpu.bus.ppu.pfc.fifo.size == 0 {
-            panic!("FIFO underflow");
+    fn pipeline_push_pixel(cpu: &mut Cpu) {
+        let pfc = &mut cpu.bus.ppu.pfc;
+
+        if pfc.bg_fifo.size == 0 {
+            return;
         }
 
-        if cpu.bus.ppu.pfc.fifo.head.is_none() {
-            panic!("FIFO head is None");
+        let bg = pfc.bg_fifo.pop();
+        let obj = if pfc.obj_fifo.size > 0 {
+            Some(pfc.obj_fifo.pop())
+        } else {
+            None
+        };
+
+        if pfc.discard > 0 {
+            pfc.discard -= 1;
+            return;
         }
 
-        let head = cpu.bus.ppu.pfc.fifo.head.clone().unwrap();
-        let val = head.borrow().value;
-        cpu.bus.ppu.pfc.fifo.head = head.borrow().next.clone();
+        let color = Self::pixel_color(&cpu.bus.lcd, bg, obj);
+        let index = cpu.bus.lcd.ly as usize * XRES as usize + pfc.pushed_x as usize;
 
-        cpu.bus.ppu.pfc.fifo.size -= 1;
-        val
+        cpu.bus.ppu.video_buffer[index] = color;
+        cpu.bus.ppu.pfc.pushed_x += 1;
     }
 
-    fn pipeline_push_pixel(cpu: &mut Cpu) {
-        if cpu.bus.ppu.pfc.fifo.size > 8 {
-            let pixel_data = Self::pixel_fifo_pop(cpu);
-
-            if cpu.bus.ppu.pfc.line_x >= cpu.bus.lcd.scx % 8 {
-                cpu.bus.ppu.video_buffer[cpu
-                    .bus
-                    .ppu
-                    .pfc
-                    .pushed_x
-                    .wrapping_add((cpu.bus.lcd.ly as i32 * XRES) as u8)
-                    as usize] = pixel_data;
-
-                cpu.bus.ppu.pfc.pushed_x += 1;
+    pub fn pixel_color(lcd: &Lcd, bg: FiFoPixel, obj: Option<FiFoPixel>) -> Color {
+        let bg_color = if lcd.bgw_enabled() != 0 { bg.color } else { 0 };
+
+        if let Some(obj) = obj {
+            if obj.color != 0 && lcd.obj_enabled() != 0 && !(obj.bg_priority && bg_color != 0) {
+                return lcd.obj_color(obj.palette, obj.color);
             }
+        }
 
-            cpu.bus.ppu.pfc.line_x += 1;
+        if lcd.bgw_enabled() == 0 {
+            return TILE_COLORS[0];
         }
+
+        lcd.bg_color(bg_color)
     }
 
+    // Background/window fetcher: reading the tile number and both data bytes
+    // take 2 dots each, pushing is retried every dot until the FIFO is empty.
     fn pipeline_fetch(cpu: &mut Cpu) {
-        let fetch_state = &cpu.bus.ppu.pfc.current_fetch_state;
+        let ppu = &mut cpu.bus.ppu;
+        let lcd = &cpu.bus.lcd;
+
+        if !matches!(ppu.pfc.current_fetch_state, FetchState::Push) {
+            ppu.pfc.fetch_ticks += 1;
+
+            if ppu.pfc.fetch_ticks < 2 {
+                return;
+            }
 
-        match fetch_state {
+            ppu.pfc.fetch_ticks = 0;
+        }
+
+        let tile_y = if ppu.pfc.window_active {
+            ppu.window_line % 8
+        } else {
+            lcd.ly.wrapping_add(lcd.scy) % 8
+        } as u16;
+
+        match ppu.pfc.current_fetch_state {
             FetchState::Tile => {
-                if cpu.bus.lcd.bgw_enabled() != 0 {
-                    let address = cpu.bus.lcd.bg_map_area()
-                        + (cpu.bus.ppu.pfc.map_x / 8) as u16
-                        + ((cpu.bus.ppu.pfc.map_y / 8) as u16 * 32);
-                    cpu.bus.ppu.pfc.bgw_fetch_data[0] = Bus::read(cpu, address);
-
-                    if cpu.bus.lcd.bgw_data_area() == 0x8800 {
-                        cpu.bus.ppu.pfc.bgw_fetch_data[0] =
-                            cpu.bus.ppu.pfc.bgw_fetch_data[0].wrapping_add(128);
-                    }
+                let address = if ppu.pfc.window_active {
+                    lcd.window_map_area()
+                        + (ppu.pfc.fetch_x / 8) as u16
+                        + (ppu.window_line / 8) as u16 * 32
+                } else {
+                    lcd.bg_map_area()
+                        + ((lcd.scx / 8).wrapping_add(ppu.pfc.fetch_x / 8) & 31) as u16
+                        + (lcd.ly.wrapping_add(lcd.scy) / 8) as u16 * 32
+                };
+                ppu.pfc.bgw_fetch_data[0] = Self::vram_read(ppu, address);
+
+                if lcd.bgw_data_area() == 0x8800 {
+                    ppu.pfc.bgw_fetch_data[0] = ppu.pfc.bgw_fetch_data[0].wrapping_add(128);
                 }
 
-                cpu.bus.ppu.pfc.current_fetch_state = FetchState::Data0;
-                cpu.bus.ppu.pfc.fetch_x = cpu.bus.ppu.pfc.fetch_x.wrapping_add(8);
+                ppu.pfc.current_fetch_state = FetchState::Data0;
             }
             FetchState::Data0 => {
-                let address = cpu.bus.lcd.bgw_data_area()
-                    + (cpu.bus.ppu.pfc.bgw_fetch_data[0] as u16 * 16)
-                    + (cpu.bus.ppu.pfc.tile_y) as u16;
-                cpu.bus.ppu.pfc.bgw_fetch_data[1] = Bus::read(cpu, address);
+                let address =
+                    lcd.bgw_data_area() + (ppu.pfc.bgw_fetch_data[0] as u16 * 16) + tile_y * 2;
+                ppu.pfc.bgw_fetch_data[1] = Self::vram_read(ppu, address);
 
-                cpu.bus.ppu.pfc.current_fetch_state = FetchState::Data1;
+                ppu.pfc.current_fetch_state = FetchState::Data1;
             }
             FetchState::Data1 => {
-                let address = cpu.bus.lcd.bgw_data_area()
-                    + (cpu.bus.ppu.pfc.bgw_fetch_data[0] as u16 * 16)
-                    + (cpu.bus.ppu.pfc.tile_y + 1) as u16;
-                cpu.bus.ppu.pfc.bgw_fetch_data[2] = Bus::read(cpu, address);
-
-                cpu.bus.ppu.pfc.current_fetch_state = FetchState::Idle;
-            }
-            FetchState::Idle => {
-                cpu.bus.ppu.pfc.current_fetch_state = FetchState::Push;
+                let address =
+                    lcd.bgw_data_area() + (ppu.pfc.bgw_fetch_data[0] as u16 * 16) + tile_y * 2 + 1;
+                ppu.pfc.bgw_fetch_data[2] = Self::vram_read(ppu, address);
+
+                if ppu.pfc.first_fetch {
+                    ppu.pfc.first_fetch = false;
+                    ppu.pfc.current_fetch_state = FetchState::Tile;
+                } else {
+                    ppu.pfc.current_fetch_state = FetchState::Push;
+                }
             }
             FetchState::Push => {
                 if Self::pipeline_fifo_add(cpu) {
@@ -347,62 +676,407 @@ impl Ppu {
     }
 
     fn pipeline_fifo_add(cpu: &mut Cpu) -> bool {
-        if cpu.bus.ppu.pfc.fifo.size > 8 {
-            // FiFo is Full
+        let pfc = &mut cpu.bus.ppu.pfc;
+
+        if pfc.bg_fifo.size > 0 {
+            // FiFo is not empty yet
             return false;
         }
 
-        let x: i8 = (cpu
-            .bus
-            .ppu
-            .pfc
-            .fetch_x
-            .wrapping_sub(8 - (cpu.bus.lcd.scx % 8))) as i8;
-
         for i in 0..8 {
             let bit = 7 - i;
-            let lo: u8 = (cpu.bus.ppu.pfc.bgw_fetch_data[1] & (1 << bit) != 0) as u8;
-            let hi: u8 = ((cpu.bus.ppu.pfc.bgw_fetch_data[2] & (1 << bit)) << 1 != 0) as u8;
+            let lo = (pfc.bgw_fetch_data[1] >> bit) & 1;
+            let hi = (pfc.bgw_fetch_data[2] >> bit) & 1;
+
+            pfc.bg_fifo.push(FiFoPixel {
+                color: hi << 1 | lo,
+                ..Default::default()
+            });
+        }
 
-            let color: Color = TILE_COLORS[(hi | lo) as usize];
+        pfc.fetch_x = pfc.fetch_x.wrapping_add(8);
+        true
+    }
 
-            if x >= 0 {
-                Self::pixel_fifo_push(cpu, color);
-                cpu.bus.ppu.pfc.fifo_x += 1;
+    // Once WX is reached on a frame where LY has matched WY the background
+    // pixels are dropped and the fetcher restarts on the window, which costs
+    // 6 dots.
+    fn pipeline_window_check(cpu: &mut Cpu) {
+        let ppu = &mut cpu.bus.ppu;
+        let lcd = &cpu.bus.lcd;
+
+        if ppu.pfc.window_active
+            || !ppu.window_triggered
+            || lcd.window_enabled() == 0
+            || !ppu.pfc.pixel_ready()
+            || !Self::window_reached(lcd, ppu.pfc.pushed_x)
+        {
+            return;
+        }
+
+        if ppu.pfc.pushed_x == 0 {
+            ppu.pfc.discard = 7u8.saturating_sub(lcd.wx);
+        }
+
+        ppu.pfc.bg_fifo.clear();
+        ppu.pfc.current_fetch_state = FetchState::Tile;
+        ppu.pfc.fetch_ticks = 0;
+        ppu.pfc.fetch_x = 0;
+        ppu.pfc.window_active = true;
+    }
+
+    // Whether the window covers the pixel at `x`, on lines where it is shown
+    pub fn window_reached(lcd: &Lcd, x: u8) -> bool {
+        x as u16 + 7 >= lcd.wx as u16
+    }
+
+    // A sprite is fetched when the pixel under its left edge is about to be
+    // shifted out. Pixel output stops for 6 dots, plus however long the
+    // background fetcher needs to finish the tile under the sprite, which only
+    // the first sprite on a given tile pays (and always 5 dots at X = 0).
+    fn pipeline_sprite_check(cpu: &mut Cpu) -> bool {
+        let ppu = &mut cpu.bus.ppu;
+        let lcd = &cpu.bus.lcd;
+
+        if lcd.obj_enabled() == 0 || ppu.pfc.discard > 0 || !ppu.pfc.pixel_ready() {
+            return false;
+        }
+
+        let mut next: Option<usize> = None;
+
+        for i in 0..ppu.pfc.sprite_count {
+            let sprite = ppu.pfc.line_sprites[i];
+
+            if ppu.pfc.sprites_fetched & (1 << i) != 0
+                || sprite.x as u16 > ppu.pfc.pushed_x as u16 + 8
+            {
+                continue;
+            }
+
+            if next.is_none_or(|n| sprite.x < ppu.pfc.line_sprites[n].x) {
+                next = Some(i);
             }
         }
 
+        let Some(index) = next else {
+            return false;
+        };
+
+        let sprite = ppu.pfc.line_sprites[index];
+        let penalty = Self::sprite_penalty(
+            lcd,
+            sprite.x,
+            ppu.pfc.window_active,
+            &mut ppu.pfc.penalty_tile,
+        );
+
+        ppu.pfc.sprites_fetched |= 1 << index;
+        ppu.pfc.sprite_fetch = index;
+        ppu.pfc.sprite_fetch_ticks = penalty;
         true
     }
 
-    fn pipeline_process(cpu: &mut Cpu) {
+    // Dots a sprite at `x` stalls mode 3 for. `penalty_tile` is the
+    // background/window tile the previous sprite on the line was fetched on.
+    pub fn sprite_penalty(
+        lcd: &Lcd,
+        x: u8,
+        window_active: bool,
+        penalty_tile: &mut Option<i16>,
+    ) -> u8 {
+        let origin = if window_active {
+            lcd.wx as i16 - 7
+        } else {
+            -((lcd.scx % 8) as i16)
+        };
+        let position = x as i16 - 8 - origin;
+        let tile = position.div_euclid(8);
+
+        let mut penalty = 6;
+        if x == 0 {
+            penalty += 5;
+        } else if *penalty_tile != Some(tile) {
+            penalty += (5 - position.rem_euclid(8)).max(0) as u8;
+        }
+
+        *penalty_tile = Some(tile);
+        penalty
+    }
+
+    fn pipeline_sprite_fetch(cpu: &mut Cpu) -> bool {
+        if cpu.bus.ppu.pfc.sprite_fetch_ticks == 0 {
+            return false;
+        }
+
+        cpu.bus.ppu.pfc.sprite_fetch_ticks -= 1;
+
+        if cpu.bus.ppu.pfc.sprite_fetch_ticks == 0 {
+            Self::pipeline_sprite_load(cpu);
+        }
+
+        true
+    }
+
+    fn pipeline_sprite_load(cpu: &mut Cpu) {
         let ppu = &mut cpu.bus.ppu;
-        let lcd = &mut cpu.bus.lcd;
+        let sprite = ppu.pfc.line_sprites[ppu.pfc.sprite_fetch];
+        let row = Self::sprite_row(ppu, &cpu.bus.lcd, &sprite);
+
+        while ppu.pfc.obj_fifo.size < 8 {
+            ppu.pfc.obj_fifo.push(FiFoPixel::default());
+        }
 
-        ppu.pfc.map_y = lcd.ly.wrapping_add(lcd.scy);
-        ppu.pfc.map_x = ppu.pfc.fetch_x.wrapping_add(lcd.scx);
-        ppu.pfc.tile_y = ((lcd.ly.wrapping_add(lcd.scy)) % 8) * 2;
+        for (i, pixel) in row.into_iter().enumerate() {
+            let slot = sprite.x as i16 - 8 + i as i16 - ppu.pfc.pushed_x as i16;
+            if slot < 0 {
+                continue;
+            }
 
-        if ppu.line_ticks & 1 == 0 {
-            Self::pipeline_fetch(cpu);
+            // Sprites fetched earlier (smaller X, or lower OAM index) win
+            let entry = ppu.pfc.obj_fifo.entry(slot as usize);
+            if pixel.color != 0 && entry.color == 0 {
+                *entry = pixel;
+            }
         }
+    }
 
-        Self::pipeline_push_pixel(cpu);
+    // The 8 pixels of a sprite on the current line, left to right
+    pub fn sprite_row(ppu: &Ppu, lcd: &Lcd, sprite: &OamEntry) -> [FiFoPixel; 8] {
+        let height = lcd.obj_height();
+        let mut tile = sprite.tile;
+        if height == 16 {
+            tile &= 0xFE;
+        }
+
+        // The height can change between the OAM scan and the fetch, only
+        // the row bits of the current height are used
+        let mut row = lcd.ly.wrapping_add(16).wrapping_sub(sprite.y) & (height - 1);
+        if sprite.flags & 0b0100_0000 != 0 {
+            // Y flip
+            row = height - 1 - row;
+        }
+
+        let address = 0x8000 + tile as u16 * 16 + row as u16 * 2;
+        let lo = Self::vram_read(ppu, address);
+        let hi = Self::vram_read(ppu, address + 1);
+
+        let mut pixels = [FiFoPixel::default(); 8];
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            let bit = if sprite.flags & 0b0010_0000 != 0 {
+                // X flip
+                i
+            } else {
+                7 - i
+            };
+
+            *pixel = FiFoPixel {
+                color: ((hi >> bit) & 1) << 1 | ((lo >> bit) & 1),
+                palette: (sprite.flags >> 4) & 1,
+                bg_priority: sprite.flags & 0b1000_0000 != 0,
+            };
+        }
+
+        pixels
     }
 
-    fn pipeline_fifo_reset(cpu: &mut Cpu) {
-        while cpu.bus.ppu.pfc.fifo.size > 0 {
-            Self::pixel_fifo_pop(cpu);
+    fn pipeline_process(cpu: &mut Cpu) {
+        if Self::pipeline_sprite_fetch(cpu) {
+            return;
         }
 
-        cpu.bus.ppu.pfc.fifo.head = None;
+        Self::pipeline_window_check(cpu);
+
+        if Self::pipeline_sprite_check(cpu) {
+            Self::pipeline_sprite_fetch(cpu);
+            return;
+        }
+
+        Self::pipeline_fetch(cpu);
+        Self::pipeline_push_pixel(cpu);
+    }
+
+    fn pipeline_fifo_reset(cpu: &mut Cpu) {
+        cpu.bus.ppu.pfc.bg_fifo.clear();
+        cpu.bus.ppu.pfc.obj_fifo.clear();
+        cpu.bus.ppu.pfc.sprite_count = 0;
+        cpu.bus.ppu.pfc.sprite_fetch_ticks = 0;
     }
 }
 
+#[derive(PartialEq, Clone, Copy)]
+pub enum Renderer {
+    // Dot by dot pixel FIFO
+    Fifo,
+    // Whole line drawn at the start of HBlank from the registers at that
+    // point, mid-line register writes are not visible.
+    Scanline,
+}
+
+#[derive(Clone, Copy)]
 pub enum FetchState {
     Tile,
     Data0,
     Data1,
-    Idle,
     Push,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Ppu, Renderer, LINES_PER_FRAME, TICKS_PER_LINE, YRES};
+    use crate::modules::{
+        cpu::Cpu,
+        lcd::{LCDMode, Lcd, StatSrc},
+    };
+
+    const STAT_IF: u8 = 0x02;
+
+    // LCD on with the background and sprites, line 0 about to start
+    fn line_0() -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.bus.lcd.lcdc = 0x83;
+        cpu.bus.lcd.wy = 0xFF;
+        cpu.bus.lcd.set_lcds_mode(LCDMode::Oam);
+        cpu
+    }
+
+    fn sprites_at(cpu: &mut Cpu, xs: &[u8]) {
+        for (sprite, &x) in cpu.bus.ppu.oam_ram.chunks_exact_mut(4).zip(xs) {
+            sprite[0] = 16;
+            sprite[1] = x;
+        }
+    }
+
+    // Dots from the end of the OAM scan to HBlank
+    fn mode_3_length(cpu: &mut Cpu) -> u32 {
+        while cpu.bus.lcd.get_lcds_mode() != LCDMode::Hblank {
+            Ppu::tick(cpu);
+        }
+
+        cpu.bus.ppu.line_ticks - 80
+    }
+
+    #[test]
+    fn mode_3_grows_with_scx() {
+        for (scx, length) in [(0, 172), (1, 173), (5, 177), (8, 172), (15, 179)] {
+            let mut cpu = line_0();
+            cpu.bus.lcd.scx = scx;
+            assert_eq!(mode_3_length(&mut cpu), length, "SCX {}", scx);
+        }
+    }
+
+    #[test]
+    fn mode_3_grows_with_sprites() {
+        let cases: [(&[u8], u8, u32); 7] = [
+            (&[], 0, 172),
+            // X = 0 always waits for the whole fetch
+            (&[0], 0, 183),
+            (&[0], 3, 186),
+            // The first sprite on a tile waits for the fetcher to finish it
+            (&[8], 0, 183),
+            (&[12], 0, 179),
+            (&[8, 8], 0, 189),
+            (&[0; 10], 0, 282),
+        ];
+
+        for (xs, scx, length) in cases {
+            let mut cpu = line_0();
+            cpu.bus.lcd.scx = scx;
+            sprites_at(&mut cpu, xs);
+            assert_eq!(mode_3_length(&mut cpu), length, "sprites {:?}", xs);
+        }
+    }
+
+    #[test]
+    fn mode_3_grows_with_the_window() {
+        for (wx, length) in [(7, 178), (80, 178), (0, 185), (166, 178), (167, 172)] {
+            let mut cpu = line_0();
+            cpu.bus.lcd.lcdc |= 0x20;
+            cpu.bus.lcd.wy = 0;
+            cpu.bus.lcd.wx = wx;
+            assert_eq!(mode_3_length(&mut cpu), length, "WX {}", wx);
+        }
+    }
+
+    #[test]
+    fn scanline_renderer_ends_mode_3_on_the_same_dot() {
+        let lengths = [Renderer::Fifo, Renderer::Scanline].map(|renderer| {
+            let mut cpu = line_0();
+            cpu.bus.ppu.renderer = renderer;
+            cpu.bus.lcd.scx = 3;
+            sprites_at(&mut cpu, &[0, 20, 20, 100]);
+            mode_3_length(&mut cpu)
+        });
+
+        assert_eq!(lengths[0], lengths[1]);
+    }
+
+    fn dots(cpu: &mut Cpu, dots: u32) {
+        for _ in 0..dots {
+            Ppu::tick(cpu);
+        }
+    }
+
+    #[test]
+    fn lcd_off_stops_on_line_0_in_mode_0() {
+        let mut cpu = line_0();
+        dots(&mut cpu, 50 * TICKS_PER_LINE + 100);
+        assert_eq!(cpu.bus.lcd.ly, 50);
+        assert!(cpu.bus.lcd.get_lcds_mode() == LCDMode::Xfer);
+
+        Lcd::write(&mut cpu, 0xFF40, 0x03);
+        assert_eq!(cpu.bus.lcd.ly, 0);
+        assert!(cpu.bus.lcd.get_lcds_mode() == LCDMode::Hblank);
+        assert!(cpu.bus.ppu.blank);
+
+        dots(&mut cpu, TICKS_PER_LINE);
+        assert_eq!(cpu.bus.lcd.ly, 0);
+        assert_eq!(cpu.bus.ppu.line_ticks, 0);
+    }
+
+    #[test]
+    fn lcd_on_starts_line_0_without_an_oam_scan() {
+        let mut cpu = line_0();
+        Lcd::write(&mut cpu, 0xFF40, 0x03);
+        cpu.bus.lcd.lyc = 0;
+        cpu.bus.lcd.lcds |= StatSrc::Lyc as u8 | StatSrc::Oam as u8;
+        cpu.interrupt_flags = 0;
+
+        // LY=LYC holds the STAT line as soon as the LCD is back on
+        Lcd::write(&mut cpu, 0xFF40, 0x83);
+        assert_eq!(cpu.bus.lcd.ly, 0);
+        assert_eq!(cpu.bus.lcd.lyc(), 0b100);
+        assert!(cpu.bus.lcd.get_lcds_mode() == LCDMode::Hblank);
+        assert_eq!(cpu.interrupt_flags & STAT_IF, STAT_IF);
+
+        // Mode 0 where the OAM scan would be, then mode 3 on dot 80
+        dots(&mut cpu, 79);
+        assert!(cpu.bus.lcd.get_lcds_mode() == LCDMode::Hblank);
+        dots(&mut cpu, 1);
+        assert!(cpu.bus.lcd.get_lcds_mode() == LCDMode::Xfer);
+
+        dots(&mut cpu, 172);
+        assert!(cpu.bus.lcd.get_lcds_mode() == LCDMode::Hblank);
+
+        // Line 1 is a normal one
+        dots(&mut cpu, TICKS_PER_LINE - 80 - 172);
+        assert_eq!(cpu.bus.lcd.ly, 1);
+        assert!(cpu.bus.lcd.get_lcds_mode() == LCDMode::Oam);
+    }
+
+    #[test]
+    fn first_frame_after_lcd_on_is_not_shown() {
+        let mut cpu = line_0();
+        Lcd::write(&mut cpu, 0xFF40, 0x03);
+        Lcd::write(&mut cpu, 0xFF40, 0x83);
+        let frame = cpu.bus.ppu.current_frame;
+
+        // Still blank once the first frame reached VBlank
+        dots(&mut cpu, YRES as u32 * TICKS_PER_LINE);
+        assert_eq!(cpu.bus.ppu.current_frame, frame + 1);
+        assert!(cpu.bus.ppu.blank);
+
+        dots(&mut cpu, LINES_PER_FRAME * TICKS_PER_LINE);
+        assert_eq!(cpu.bus.ppu.current_frame, frame + 2);
+        assert!(!cpu.bus.ppu.blank);
+    }
+}
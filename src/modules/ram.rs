@@ -1,3 +1,5 @@
+use super::savestate::{StateReader, StateWriter};
+
 pub struct Ram {
     wram: [u8; 0x2000],
     hram: [u8; 0x80],
@@ -34,4 +36,14 @@ impl Ram {
 
         self.hram[address as usize] = value;
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.wram);
+        state.bytes(&self.hram);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.wram)?;
+        state.bytes(&mut self.hram)
+    }
 }
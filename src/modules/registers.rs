@@ -1,5 +1,7 @@
 use crate::modules::common;
 
+use super::savestate::{StateReader, StateWriter};
+
 #[derive(Clone, Copy)]
 pub struct Registers {
     pub a: u8,
@@ -45,4 +47,33 @@ impl Registers {
     pub fn flag_c(&self) -> bool {
         common::bit(self.f, 4)
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&[
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l,
+        ]);
+        state.u16(self.pc);
+        state.u16(self.sp);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let mut regs = [0; 8];
+        state.bytes(&mut regs)?;
+
+        let [a, f, b, c, d, e, h, l] = regs;
+        *self = Self {
+            a,
+            f,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            pc: state.u16()?,
+            sp: state.u16()?,
+        };
+
+        Ok(())
+    }
 }
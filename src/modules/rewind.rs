@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+
+use super::{cpu::Cpu, savestate::SaveState};
+
+// History of machine states to step back through. Only the newest state is
+// kept whole, every older one is stored as the run-length encoded XOR with
+// the state after it. Consecutive frames differ in few bytes, so those
+// deltas are mostly zero runs.
+pub struct Rewind {
+    current: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    // Bytes held by current and deltas
+    size: usize,
+    budget: usize,
+
+    // A state is taken every this many frames
+    interval: u32,
+    frames: u32,
+}
+
+impl Rewind {
+    pub fn new(budget: usize, interval: u32) -> Self {
+        Self {
+            current: None,
+            deltas: VecDeque::new(),
+            size: 0,
+            budget,
+            interval: interval.max(1),
+            frames: 0,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.budget > 0
+    }
+
+    // Called after every emulated frame
+    pub fn record(&mut self, cpu: &Cpu) {
+        if !self.enabled() {
+            return;
+        }
+
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+
+        let state = SaveState::snapshot(cpu);
+
+        if let Some(previous) = self.current.take() {
+            self.size -= previous.len();
+
+            if previous.len() == state.len() {
+                let delta = Self::encode(&previous, &state);
+                self.size += delta.len();
+                self.deltas.push_back(delta);
+            } else {
+                self.clear();
+            }
+        }
+
+        self.size += state.len();
+        self.current = Some(state);
+
+        // The oldest delta is only needed to reach the oldest state
+        while self.size > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.size -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    // Restores the state before the newest one, false when the history is
+    // used up.
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> bool {
+        let (current, delta) = match (&mut self.current, self.deltas.pop_back()) {
+            (Some(current), Some(delta)) => (current, delta),
+            _ => return false,
+        };
+
+        self.size -= delta.len();
+        Self::decode(&delta, current);
+        self.frames = 0;
+
+        SaveState::restore(cpu, current).is_ok()
+    }
+
+    pub fn clear(&mut self) {
+        self.current = None;
+        self.deltas.clear();
+        self.size = 0;
+        self.frames = 0;
+    }
+
+    // Number of states that can be stepped back to
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    // XOR of both states as a sequence of (zero run, literal count,
+    // literals), the counts as LEB128.
+    fn encode(old: &[u8], new: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut i = 0;
+
+        while i < old.len() {
+            let start = i;
+            while i < old.len() && old[i] == new[i] {
+                i += 1;
+            }
+            let zeros = i - start;
+
+            let start = i;
+            while i < old.len() && old[i] != new[i] {
+                i += 1;
+            }
+
+            Self::write_len(&mut out, zeros);
+            Self::write_len(&mut out, i - start);
+            out.extend(old[start..i].iter().zip(&new[start..i]).map(|(a, b)| a ^ b));
+        }
+
+        out
+    }
+
+    // Applies a delta from encode to the newer state, which turns it back
+    // into the older one.
+    fn decode(delta: &[u8], state: &mut [u8]) {
+        let mut pos = 0;
+        let mut i = 0;
+
+        while pos < delta.len() {
+            i += Self::read_len(delta, &mut pos);
+            let literals = Self::read_len(delta, &mut pos);
+
+            for byte in &mut state[i..i + literals] {
+                *byte ^= delta[pos];
+                pos += 1;
+            }
+            i += literals;
+        }
+    }
+
+    fn write_len(out: &mut Vec<u8>, mut len: usize) {
+        while len >= 0x80 {
+            out.push(len as u8 | 0x80);
+            len >>= 7;
+        }
+        out.push(len as u8);
+    }
+
+    fn read_len(data: &[u8], pos: &mut usize) -> usize {
+        let mut len = 0;
+        let mut shift = 0;
+
+        loop {
+            let byte = data[*pos];
+            *pos += 1;
+
+            len |= ((byte & 0x7F) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return len;
+            }
+            shift += 7;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rewind;
+
+    // Decoding the delta against the newer state gives back the older one
+    fn round_trip(old: &[u8], new: &[u8]) -> Vec<u8> {
+        let delta = Rewind::encode(old, new);
+
+        let mut state = new.to_vec();
+        Rewind::decode(&delta, &mut state);
+        assert_eq!(state, old);
+
+        delta
+    }
+
+    #[test]
+    fn delta_of_equal_states_is_one_zero_run() {
+        let state = vec![0x42; 1000];
+        // 1000 in two LEB128 bytes, then no literals
+        assert_eq!(round_trip(&state, &state), [0xE8, 0x07, 0x00]);
+    }
+
+    #[test]
+    fn deltas_restore_the_older_state() {
+        let old: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+
+        // A change at either end, a long run of changes whose count takes
+        // more than one LEB128 byte, and a few scattered bytes
+        let mut new = old.clone();
+        new[0] ^= 0xFF;
+        for byte in &mut new[5000..5300] {
+            *byte = byte.wrapping_add(1);
+        }
+        for i in (10_000..11_000).step_by(97) {
+            new[i] = 0;
+        }
+        new[19_999] ^= 0x01;
+
+        let delta = round_trip(&old, &new);
+        assert!(delta.len() < 400);
+
+        // Every byte different
+        let new: Vec<u8> = old.iter().map(|byte| !byte).collect();
+        round_trip(&old, &new);
+    }
+}
@@ -0,0 +1,263 @@
+use std::{fs, path::Path};
+
+use super::cpu::Cpu;
+
+const MAGIC: &[u8; 4] = b"GBSS";
+// Bump whenever the layout of any component's state changes, older states
+// are rejected rather than misread.
+pub const STATE_VERSION: u16 = 6;
+
+// Header: magic, version, checksum of the ROM the state was taken from
+const HEADER_SIZE: usize = 4 + 2 + 4;
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self { data: Vec::new() }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.data.extend_from_slice(value);
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() - self.pos < len {
+            return Err(String::from("state is truncated"));
+        }
+
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), String> {
+        out.copy_from_slice(self.take(out.len())?);
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        if self.pos != self.data.len() {
+            return Err(String::from("state has trailing data"));
+        }
+
+        Ok(())
+    }
+}
+
+// Snapshot of the whole machine. Each component writes its own fields in
+// save_state and reads them back in the same order in load_state.
+pub struct SaveState {}
+
+impl SaveState {
+    pub fn snapshot(cpu: &Cpu) -> Vec<u8> {
+        let mut state = StateWriter::new();
+
+        state.bytes(MAGIC);
+        state.u16(STATE_VERSION);
+        state.u32(cpu.bus.cart.rom_checksum());
+
+        cpu.save_state(&mut state);
+        cpu.bus.ram.save_state(&mut state);
+        cpu.bus.io.save_state(&mut state);
+        cpu.bus.joypad.save_state(&mut state);
+        cpu.bus.lcd.save_state(&mut state);
+        cpu.bus.ppu.save_state(&mut state);
+        cpu.bus.cart.save_state(&mut state);
+        cpu.timer.save_state(&mut state);
+        cpu.dma.save_state(&mut state);
+
+        state.into_vec()
+    }
+
+    // Leaves the machine untouched if the state can't be loaded
+    pub fn restore(cpu: &mut Cpu, data: &[u8]) -> Result<(), String> {
+        Self::check_header(cpu, data)?;
+
+        let backup = Self::snapshot(cpu);
+
+        if let Err(err) = Self::load_body(cpu, &data[HEADER_SIZE..]) {
+            // A truncated or corrupt body may have been partially applied
+            Self::load_body(cpu, &backup[HEADER_SIZE..])
+                .map_err(|backup_err| format!("{}, then restoring failed: {}", err, backup_err))?;
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    fn check_header(cpu: &Cpu, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new(data);
+
+        let mut magic = [0; 4];
+        state
+            .bytes(&mut magic)
+            .map_err(|_| String::from("not a save state"))?;
+
+        if &magic != MAGIC {
+            return Err(String::from("not a save state"));
+        }
+
+        let version = state.u16()?;
+        if version != STATE_VERSION {
+            return Err(format!(
+                "state version {} is not supported (expected {})",
+                version, STATE_VERSION
+            ));
+        }
+
+        if state.u32()? != cpu.bus.cart.rom_checksum() {
+            return Err(String::from("state was saved from a different ROM"));
+        }
+
+        Ok(())
+    }
+
+    fn load_body(cpu: &mut Cpu, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new(data);
+
+        cpu.load_state(&mut state)?;
+        cpu.bus.ram.load_state(&mut state)?;
+        cpu.bus.io.load_state(&mut state)?;
+        cpu.bus.joypad.load_state(&mut state)?;
+        cpu.bus.lcd.load_state(&mut state)?;
+        cpu.bus.ppu.load_state(&mut state)?;
+        cpu.bus.cart.load_state(&mut state)?;
+        cpu.timer.load_state(&mut state)?;
+        cpu.dma.load_state(&mut state)?;
+
+        state.finish()
+    }
+
+    pub fn save(cpu: &Cpu, path: &str) -> Result<(), String> {
+        fs::write(path, Self::snapshot(cpu)).map_err(|err| err.to_string())
+    }
+
+    pub fn load(cpu: &mut Cpu, path: &str) -> Result<(), String> {
+        let data = fs::read(path).map_err(|err| err.to_string())?;
+        Self::restore(cpu, &data)
+    }
+
+    // roms/game.gb slot 1 -> roms/game.ss1
+    pub fn slot_path(rom_path: &str, slot: u8) -> String {
+        Path::new(rom_path)
+            .with_extension(format!("ss{}", slot))
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SaveState, HEADER_SIZE};
+    use crate::modules::{cpu::Cpu, emu::Emu, options::Options, pacer::CPU_FREQUENCY};
+
+    fn run_for(cpu: &mut Cpu, ticks: u64) {
+        let end = cpu.timer.ticks + ticks;
+        while cpu.timer.ticks < end {
+            assert!(cpu.step());
+        }
+    }
+
+    // dmg-acid2 a few frames in, with the PPU, timer and cartridge running
+    fn booted() -> Cpu {
+        let rom = format!("{}/roms/dmg-acid2.gb", env!("CARGO_MANIFEST_DIR"));
+        let options = Options::parse(&[String::from("gameboy_emulator"), rom.clone()]);
+        let mut cpu = Emu::boot(&options, &rom);
+
+        run_for(&mut cpu, CPU_FREQUENCY / 20);
+        cpu
+    }
+
+    #[test]
+    fn restoring_a_snapshot_gives_the_same_snapshot_back() {
+        let mut cpu = booted();
+        let state = SaveState::snapshot(&cpu);
+
+        run_for(&mut cpu, CPU_FREQUENCY / 20);
+        assert_ne!(SaveState::snapshot(&cpu), state);
+
+        SaveState::restore(&mut cpu, &state).unwrap();
+        assert_eq!(SaveState::snapshot(&cpu), state);
+    }
+
+    #[test]
+    fn bad_states_leave_the_machine_untouched() {
+        let mut cpu = booted();
+        let state = SaveState::snapshot(&cpu);
+
+        run_for(&mut cpu, CPU_FREQUENCY / 20);
+        let before = SaveState::snapshot(&cpu);
+
+        // Truncated body, applied partway before it fails
+        let truncated = &state[..state.len() - 1];
+        assert!(SaveState::restore(&mut cpu, truncated).is_err());
+        assert_eq!(SaveState::snapshot(&cpu), before);
+
+        // Another ROM's state
+        let mut other = state.clone();
+        other[HEADER_SIZE - 1] ^= 0xFF;
+        assert_eq!(
+            SaveState::restore(&mut cpu, &other),
+            Err(String::from("state was saved from a different ROM"))
+        );
+        assert_eq!(SaveState::snapshot(&cpu), before);
+    }
+}
@@ -0,0 +1,225 @@
+use sdl2::pixels::Color;
+
+use super::{
+    cpu::Cpu,
+    lcd::Lcd,
+    ppu::{FiFoPixel, OamEntry, Ppu},
+};
+
+const XRES: i32 = 160;
+
+pub struct Scanline {}
+
+impl Scanline {
+    // How long the FIFO renderer would spend in mode 3 on this line, so
+    // HBlank and the STAT interrupts land on the same dot with either
+    // renderer.
+    pub fn xfer_length(cpu: &Cpu) -> u32 {
+        let lcd = &cpu.bus.lcd;
+        let ppu = &cpu.bus.ppu;
+        let window = Self::window_visible(cpu);
+
+        let mut length = 172;
+
+        if window && lcd.wx <= 7 {
+            // The window starts on the first pixel and replaces the SCX
+            // fine scroll with its own.
+            length += 6 + (7 - lcd.wx) as u32;
+        } else {
+            length += (lcd.scx % 8) as u32;
+
+            if window {
+                length += 6;
+            }
+        }
+
+        if lcd.obj_enabled() == 0 {
+            return length;
+        }
+
+        let sprites = &ppu.pfc.line_sprites[..ppu.pfc.sprite_count];
+        let mut penalty_tile = None;
+
+        for &index in Self::sprite_order(sprites).iter().take(sprites.len()) {
+            let x = sprites[index].x;
+            if x >= 168 {
+                continue;
+            }
+
+            // The sprite is fetched once the pixel under its left edge is
+            // next, like in the FIFO
+            let window_active = window && Ppu::window_reached(lcd, x.saturating_sub(8));
+            length += Ppu::sprite_penalty(lcd, x, window_active, &mut penalty_tile) as u32;
+        }
+
+        length
+    }
+
+    pub fn render_line(cpu: &mut Cpu) {
+        let window = Self::window_visible(cpu);
+
+        let lcd = &cpu.bus.lcd;
+        let ppu = &cpu.bus.ppu;
+        let sprites = &ppu.pfc.line_sprites[..ppu.pfc.sprite_count];
+        let order = Self::sprite_order(sprites);
+
+        let mut rows = [[FiFoPixel::default(); 8]; 10];
+        for (row, sprite) in rows.iter_mut().zip(sprites) {
+            *row = Ppu::sprite_row(ppu, lcd, sprite);
+        }
+
+        let mut line = [Color::RGB(255, 255, 255); XRES as usize];
+
+        for (x, pixel) in line.iter_mut().enumerate() {
+            let x = x as u8;
+
+            let color = if window && Ppu::window_reached(lcd, x) {
+                Self::tile_pixel(
+                    ppu,
+                    lcd,
+                    lcd.window_map_area(),
+                    x + 7 - lcd.wx,
+                    ppu.window_line,
+                )
+            } else {
+                Self::tile_pixel(
+                    ppu,
+                    lcd,
+                    lcd.bg_map_area(),
+                    x.wrapping_add(lcd.scx),
+                    lcd.ly.wrapping_add(lcd.scy),
+                )
+            };
+            let bg = FiFoPixel {
+                color,
+                ..Default::default()
+            };
+
+            // Smallest X wins, then lowest OAM index, like the sprite FIFO
+            let obj = order
+                .iter()
+                .take(sprites.len())
+                .filter_map(|&index| {
+                    let column = x as i16 + 8 - sprites[index].x as i16;
+                    (0..8)
+                        .contains(&column)
+                        .then(|| rows[index][column as usize])
+                })
+                .find(|pixel| pixel.color != 0);
+
+            *pixel = Ppu::pixel_color(lcd, bg, obj);
+        }
+
+        let start = cpu.bus.lcd.ly as usize * XRES as usize;
+        cpu.bus.ppu.video_buffer[start..start + XRES as usize].copy_from_slice(&line);
+        cpu.bus.ppu.pfc.window_active = window;
+    }
+
+    fn window_visible(cpu: &Cpu) -> bool {
+        cpu.bus.ppu.window_triggered && cpu.bus.lcd.window_enabled() != 0 && cpu.bus.lcd.wx <= 166
+    }
+
+    // Indices into the line's sprites sorted by X, then by OAM order
+    fn sprite_order(sprites: &[OamEntry]) -> [usize; 10] {
+        let mut order = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        order[..sprites.len()].sort_unstable_by_key(|&index| (sprites[index].x, index));
+        order
+    }
+
+    fn tile_pixel(ppu: &Ppu, lcd: &Lcd, map_area: u16, x: u8, y: u8) -> u8 {
+        let mut tile = Ppu::vram_read(ppu, map_area + (x / 8) as u16 + (y / 8) as u16 * 32);
+
+        if lcd.bgw_data_area() == 0x8800 {
+            tile = tile.wrapping_add(128);
+        }
+
+        let address = lcd.bgw_data_area() + tile as u16 * 16 + (y % 8) as u16 * 2;
+        Self::color_index(ppu, address, 7 - x % 8)
+    }
+
+    fn color_index(ppu: &Ppu, address: u16, bit: u8) -> u8 {
+        let lo = (Ppu::vram_read(ppu, address) >> bit) & 1;
+        let hi = (Ppu::vram_read(ppu, address + 1) >> bit) & 1;
+
+        hi << 1 | lo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::pixels::Color;
+
+    use super::XRES;
+    use crate::modules::{
+        cpu::Cpu,
+        lcd::{LCDMode, Lcd},
+        ppu::{Ppu, Renderer},
+    };
+
+    // xorshift32, enough to shake out the registers and memory of a line
+    struct Random(u32);
+
+    impl Random {
+        fn next(&mut self) -> u8 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            (self.0 >> 24) as u8
+        }
+    }
+
+    // Draws one line from random VRAM, OAM and registers, and returns how
+    // long mode 3 took, the pixels and the window line counter after it
+    fn draw_line(seed: u32, renderer: Renderer) -> (u32, Vec<Color>, u8) {
+        let mut random = Random(seed);
+        let mut cpu = Cpu::new();
+
+        for byte in cpu.bus.ppu.vram.iter_mut() {
+            *byte = random.next();
+        }
+
+        let ly = random.next() % 144;
+        for sprite in cpu.bus.ppu.oam_ram.chunks_exact_mut(4) {
+            sprite[0] = ly.wrapping_add(random.next() % 32);
+            // Crowd the sprites together so they share tiles
+            sprite[1] = random.next() % 176;
+            sprite[2] = random.next();
+            sprite[3] = random.next();
+        }
+
+        cpu.bus.lcd.lcdc = random.next() | 0x80;
+        cpu.bus.lcd.ly = ly;
+        cpu.bus.lcd.scx = random.next();
+        cpu.bus.lcd.scy = random.next();
+        cpu.bus.lcd.wy = if random.next() < 0x80 { ly } else { 0xFF };
+        cpu.bus.lcd.wx = random.next() % 176;
+        for address in 0xFF47..=0xFF49 {
+            Lcd::write(&mut cpu, address, random.next());
+        }
+
+        cpu.bus.ppu.window_line = random.next() % 144;
+        cpu.bus.ppu.renderer = renderer;
+        cpu.bus.lcd.set_lcds_mode(LCDMode::Oam);
+
+        while cpu.bus.lcd.get_lcds_mode() != LCDMode::Hblank {
+            Ppu::tick(&mut cpu);
+        }
+
+        let start = ly as usize * XRES as usize;
+        let line = cpu.bus.ppu.video_buffer[start..start + XRES as usize].to_vec();
+
+        (cpu.bus.ppu.line_ticks - 80, line, cpu.bus.ppu.window_line)
+    }
+
+    #[test]
+    fn matches_the_fifo_renderer_on_random_lines() {
+        for seed in 1..=3000 {
+            assert_eq!(
+                draw_line(seed, Renderer::Scanline),
+                draw_line(seed, Renderer::Fifo),
+                "seed {}",
+                seed
+            );
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use super::{common::timestamped_path, cpu::Cpu};
+
+const XRES: u32 = 160;
+const YRES: u32 = 144;
+
+// Saves the screen as it is shown, at native resolution or every pixel
+// blown up to a scale x scale block like the window draws it, and compares
+// it against reference images to catch rendering regressions.
+pub struct Screenshot {}
+
+impl Screenshot {
+    // RGB24 pixels of the screen at the given scale, white while it's blank
+    pub fn pixels(cpu: &Cpu, scale: u32) -> Vec<u8> {
+        let width = XRES * scale;
+        let mut pixels = Vec::with_capacity((width * YRES * scale * 3) as usize);
+
+        for y in 0..YRES * scale {
+            for x in 0..width {
+                let color = cpu.bus.ppu.video_buffer[((y / scale) * XRES + x / scale) as usize];
+
+                if cpu.bus.ppu.blank {
+                    pixels.extend_from_slice(&[0xFF, 0xFF, 0xFF]);
+                } else {
+                    pixels.extend_from_slice(&[color.r, color.g, color.b]);
+                }
+            }
+        }
+
+        pixels
+    }
+
+    pub fn save(cpu: &Cpu, path: &str, scale: u32) -> Result<(), String> {
+        Self::write(path, XRES * scale, YRES * scale, &Self::pixels(cpu, scale))
+    }
+
+    // Width, height and RGB24 pixels of a PNG, whatever its color type
+    pub fn load(path: &str) -> Result<(u32, u32, Vec<u8>), String> {
+        let error = |err: &dyn std::fmt::Display| format!("{}: {}", path, err);
+
+        let file = File::open(path).map_err(|err| error(&err))?;
+        let mut decoder = png::Decoder::new(file);
+        // Palettes and bit depths below 8 come out as plain 8-bit samples
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+        let mut reader = decoder.read_info().map_err(|err| error(&err))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(|err| error(&err))?;
+        let samples = &buffer[..info.buffer_size()];
+
+        let pixels = match info.color_type {
+            png::ColorType::Rgb => samples.to_vec(),
+            png::ColorType::Rgba => samples
+                .chunks(4)
+                .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                .collect(),
+            png::ColorType::Grayscale => samples.iter().flat_map(|&gray| [gray; 3]).collect(),
+            png::ColorType::GrayscaleAlpha => {
+                samples.chunks(2).flat_map(|pixel| [pixel[0]; 3]).collect()
+            }
+            png::ColorType::Indexed => return Err(error(&"unexpanded palette")),
+        };
+
+        Ok((info.width, info.height, pixels))
+    }
+
+    // Scale and RGB24 pixels of a reference image of the screen
+    pub fn load_reference(path: &str) -> Result<(u32, Vec<u8>), String> {
+        let (width, height, pixels) = Self::load(path)?;
+
+        let scale = width / XRES;
+        if scale == 0 || width != XRES * scale || height != YRES * scale {
+            return Err(format!(
+                "{}: {}x{} isn't the size of the screen",
+                path, width, height
+            ));
+        }
+
+        Ok((scale, pixels))
+    }
+
+    // Number of pixels on screen that differ from the reference image, which
+    // can be at any scale. If there are any, an image of the screen with
+    // them in red is written to diff_path.
+    pub fn compare(cpu: &Cpu, reference: &str, diff_path: &str) -> Result<usize, String> {
+        let (scale, expected) = Self::load_reference(reference)?;
+
+        let actual = Self::pixels(cpu, scale);
+        let mut diff = Vec::with_capacity(actual.len());
+        let mut differences = 0;
+
+        for (actual, expected) in actual.chunks(3).zip(expected.chunks(3)) {
+            if actual == expected {
+                // Faded, so the differences stand out
+                diff.extend(actual.iter().map(|&sample| 0xC0 + sample / 4));
+            } else {
+                differences += 1;
+                diff.extend_from_slice(&[0xFF, 0x00, 0x00]);
+            }
+        }
+
+        // Counted in screen pixels rather than reference image pixels
+        let differences = differences / (scale * scale) as usize;
+
+        if differences > 0 {
+            Self::write(diff_path, XRES * scale, YRES * scale, &diff)?;
+        }
+
+        Ok(differences)
+    }
+
+    // reference.png -> reference-diff.png
+    pub fn diff_path(reference: &str) -> String {
+        let path = Path::new(reference);
+        let name = format!(
+            "{}-diff.png",
+            path.file_stem().unwrap_or_default().to_string_lossy()
+        );
+
+        path.with_file_name(name).to_string_lossy().into_owned()
+    }
+
+    fn write(path: &str, width: u32, height: u32, pixels: &[u8]) -> Result<(), String> {
+        let file = File::create(path).map_err(|err| format!("{}: {}", path, err))?;
+
+        let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(pixels))
+            .map_err(|err| format!("{}: {}", path, err))
+    }
+
+    // roms/game.gb -> roms/game-20240131-235959.png
+    pub fn default_path(rom_path: &str) -> String {
+        timestamped_path(rom_path, "png")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Screenshot;
+    use crate::modules::{
+        bus::Bus, emu::Emu, options::Options, pacer::CPU_FREQUENCY, test_runner::MOONEYE_BREAKPOINT,
+    };
+
+    #[test]
+    fn dmg_acid2_matches_its_reference_image() {
+        let rom = format!("{}/roms/dmg-acid2.gb", env!("CARGO_MANIFEST_DIR"));
+        let options = Options::parse(&[String::from("gameboy_emulator"), rom.clone()]);
+        let mut cpu = Emu::boot(&options, &rom);
+
+        // The face is drawn once it executes LD B,B
+        while Bus::read(&cpu, cpu.registers.pc) != MOONEYE_BREAKPOINT {
+            assert!(cpu.timer.ticks < 5 * CPU_FREQUENCY, "never finished");
+            assert!(cpu.step(), "crashed");
+        }
+
+        let reference = rom.replace(".gb", ".png");
+        let diff = std::env::temp_dir().join("dmg-acid2-diff.png");
+        let differences = Screenshot::compare(&cpu, &reference, &diff.to_string_lossy());
+
+        assert_eq!(differences, Ok(0), "see {}", diff.display());
+    }
+}
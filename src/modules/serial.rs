@@ -0,0 +1,300 @@
+use crate::{enums::interrupt_types::InterruptType, modules::interrupts::interrupt};
+
+use super::cpu::Cpu;
+use super::savestate::{StateReader, StateWriter};
+
+// DIV bits whose falling edge shifts one bit with the internal clock:
+// 8192 Hz, or 262144 Hz with the CGB fast clock
+const NORMAL_CLOCK_BIT: u16 = 1 << 8;
+const FAST_CLOCK_BIT: u16 = 1 << 3;
+
+// Whatever is plugged into the other end of the link cable. Transfers are
+// exchanged a byte at a time, both ends shift out their byte while the
+// other one's shifts in.
+pub trait SerialDevice {
+    // Polled with our byte and whether we clock the transfer, when it starts
+    // and then on every clock edge until it returns the other end's byte.
+    // Nothing shifts in the meantime.
+    fn exchange(&mut self, byte: u8, clocked: bool) -> Option<u8>;
+}
+
+// The serial controller behind SB and SC
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    // Clock speed select in SC bit 1 only exists on the CGB, set from the
+    // cartridge header
+    pub cgb: bool,
+
+    // Bits left to shift in the transfer clocked by us
+    bits: u8,
+    // Byte coming from the other end, shifted into SB as ours goes out.
+    // None until the other end sent it.
+    incoming: Option<u8>,
+    // Level of the DIV bit that clocks the transfer
+    clock: bool,
+
+    // Last byte sent, for tools that read what test ROMs print
+    pub sent: Option<u8>,
+    // Nothing plugged in reads as all 1 bits
+    pub device: Option<Box<dyn SerialDevice>>,
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Self {
+            sb: 0,
+            sc: 0,
+            cgb: false,
+            bits: 0,
+            incoming: None,
+            clock: false,
+            sent: None,
+            device: None,
+        }
+    }
+
+    pub fn read(cpu: &Cpu, address: u16) -> u8 {
+        let serial = &cpu.bus.io.serial;
+
+        match address {
+            0xFF01 => serial.sb,
+            0xFF02 if serial.cgb => serial.sc | 0b0111_1100,
+            0xFF02 => serial.sc | 0b0111_1110,
+            _ => 0,
+        }
+    }
+
+    pub fn write(cpu: &mut Cpu, address: u16, value: u8) {
+        let serial = &mut cpu.bus.io.serial;
+
+        match address {
+            0xFF01 => serial.sb = value,
+            0xFF02 => {
+                serial.sc = value & if serial.cgb { 0x83 } else { 0x81 };
+                serial.bits = 0;
+                serial.incoming = None;
+
+                if serial.sc & 0x81 == 0x81 {
+                    serial.start(cpu.timer.div);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn start(&mut self, div: u16) {
+        self.sent = Some(self.sb);
+        self.bits = 8;
+        self.clock = div & self.clock_bit() != 0;
+        self.incoming = self.exchange(true);
+    }
+
+    fn clock_bit(&self) -> u16 {
+        if self.sc & 0b10 != 0 {
+            FAST_CLOCK_BIT
+        } else {
+            NORMAL_CLOCK_BIT
+        }
+    }
+
+    fn exchange(&mut self, clocked: bool) -> Option<u8> {
+        match &mut self.device {
+            Some(device) => device.exchange(self.sb, clocked),
+            None if clocked => Some(0xFF),
+            None => None,
+        }
+    }
+
+    // Called every T-cycle after the timer advanced DIV
+    pub fn tick(cpu: &mut Cpu) {
+        let serial = &mut cpu.bus.io.serial;
+
+        if serial.sc & 0x80 == 0 {
+            return;
+        }
+
+        let clock = cpu.timer.div & serial.clock_bit() != 0;
+        let falling_edge = serial.clock && !clock;
+        serial.clock = clock;
+
+        if !falling_edge {
+            return;
+        }
+
+        let clocked = serial.sc & 0x01 != 0;
+        if serial.incoming.is_none() {
+            serial.incoming = serial.exchange(clocked);
+        }
+
+        // Nothing shifts until the other end's byte is there
+        let Some(incoming) = serial.incoming else {
+            return;
+        };
+
+        if !clocked {
+            // The other end clocks the whole byte, checked at our own
+            // clock rate
+            serial.sent = Some(serial.sb);
+            serial.sb = incoming;
+            Self::complete(cpu);
+            return;
+        }
+
+        serial.sb = serial.sb << 1 | incoming >> 7;
+        serial.incoming = Some(incoming << 1);
+        serial.bits -= 1;
+
+        if serial.bits == 0 {
+            Self::complete(cpu);
+        }
+    }
+
+    fn complete(cpu: &mut Cpu) {
+        cpu.bus.io.serial.sc &= 0x7F;
+        cpu.bus.io.serial.incoming = None;
+        interrupt::request(cpu, InterruptType::Serial);
+    }
+
+    // The device is a frontend setting and stays plugged in
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&[self.sb, self.sc, self.bits]);
+        state.bool(self.incoming.is_some());
+        state.u8(self.incoming.unwrap_or(0xFF));
+        state.bool(self.clock);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.sb = state.u8()?;
+        self.sc = state.u8()?;
+        self.bits = state.u8()?;
+        let received = state.bool()?;
+        let incoming = state.u8()?;
+        self.incoming = received.then_some(incoming);
+        self.clock = state.bool()?;
+        self.sent = None;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::{Serial, SerialDevice};
+    use crate::modules::{cpu::Cpu, timer::Timer};
+
+    const SERIAL_IF: u8 = 0x08;
+    // T-cycles between two shifts with the normal internal clock
+    const SHIFT_CYCLES: u32 = 512;
+
+    // Other end that answers with whatever byte the test set, and keeps the
+    // last byte it was given
+    #[derive(Default)]
+    struct Stub {
+        reply: Cell<Option<u8>>,
+        received: Cell<Option<u8>>,
+    }
+
+    impl SerialDevice for Rc<Stub> {
+        fn exchange(&mut self, byte: u8, _clocked: bool) -> Option<u8> {
+            self.received.set(Some(byte));
+            self.reply.get()
+        }
+    }
+
+    fn plugged_in(reply: Option<u8>) -> (Cpu, Rc<Stub>) {
+        let stub = Rc::new(Stub::default());
+        stub.reply.set(reply);
+
+        let mut cpu = Cpu::new();
+        cpu.timer.div = 0;
+        cpu.bus.io.serial.device = Some(Box::new(Rc::clone(&stub)));
+
+        (cpu, stub)
+    }
+
+    fn t_cycles(cpu: &mut Cpu, cycles: u32) {
+        for _ in 0..cycles {
+            Timer::tick(cpu);
+            Serial::tick(cpu);
+        }
+    }
+
+    #[test]
+    fn internal_clock_completes_after_8_shifts() {
+        let (mut cpu, stub) = plugged_in(Some(0xA5));
+        Serial::write(&mut cpu, 0xFF01, 0x3C);
+        Serial::write(&mut cpu, 0xFF02, 0x81);
+        assert_eq!(stub.received.get(), Some(0x3C));
+
+        t_cycles(&mut cpu, 8 * SHIFT_CYCLES - 1);
+        assert_eq!(Serial::read(&cpu, 0xFF02) & 0x80, 0x80);
+        assert_eq!(cpu.interrupt_flags & SERIAL_IF, 0);
+
+        t_cycles(&mut cpu, 1);
+        assert_eq!(Serial::read(&cpu, 0xFF02) & 0x80, 0);
+        assert_eq!(Serial::read(&cpu, 0xFF01), 0xA5);
+        assert_eq!(cpu.interrupt_flags & SERIAL_IF, SERIAL_IF);
+    }
+
+    #[test]
+    fn cgb_fast_clock_shifts_32_times_faster() {
+        let (mut cpu, _) = plugged_in(Some(0xA5));
+        cpu.bus.io.serial.cgb = true;
+        Serial::write(&mut cpu, 0xFF02, 0x83);
+        assert_eq!(Serial::read(&cpu, 0xFF02), 0xFF);
+
+        t_cycles(&mut cpu, 8 * SHIFT_CYCLES / 32 - 1);
+        assert_eq!(cpu.interrupt_flags & SERIAL_IF, 0);
+
+        t_cycles(&mut cpu, 1);
+        assert_eq!(Serial::read(&cpu, 0xFF01), 0xA5);
+        assert_eq!(cpu.interrupt_flags & SERIAL_IF, SERIAL_IF);
+    }
+
+    #[test]
+    fn dmg_has_no_fast_clock() {
+        let (mut cpu, _) = plugged_in(Some(0xA5));
+        Serial::write(&mut cpu, 0xFF02, 0x83);
+        assert_eq!(Serial::read(&cpu, 0xFF02), 0xFF);
+
+        t_cycles(&mut cpu, 8 * SHIFT_CYCLES / 32);
+        assert_eq!(cpu.interrupt_flags & SERIAL_IF, 0);
+
+        t_cycles(&mut cpu, 8 * SHIFT_CYCLES - 8 * SHIFT_CYCLES / 32);
+        assert_eq!(cpu.interrupt_flags & SERIAL_IF, SERIAL_IF);
+    }
+
+    #[test]
+    fn internal_clock_with_nothing_plugged_in_reads_all_1_bits() {
+        let mut cpu = Cpu::new();
+        cpu.timer.div = 0;
+        Serial::write(&mut cpu, 0xFF01, 0x3C);
+        Serial::write(&mut cpu, 0xFF02, 0x81);
+
+        t_cycles(&mut cpu, 8 * SHIFT_CYCLES);
+        assert_eq!(Serial::read(&cpu, 0xFF01), 0xFF);
+        assert_eq!(cpu.interrupt_flags & SERIAL_IF, SERIAL_IF);
+    }
+
+    #[test]
+    fn external_clock_waits_for_the_device() {
+        let (mut cpu, stub) = plugged_in(None);
+        Serial::write(&mut cpu, 0xFF01, 0x42);
+        Serial::write(&mut cpu, 0xFF02, 0x80);
+
+        t_cycles(&mut cpu, 100 * SHIFT_CYCLES);
+        assert_eq!(stub.received.get(), Some(0x42));
+        assert_eq!(Serial::read(&cpu, 0xFF01), 0x42);
+        assert_eq!(Serial::read(&cpu, 0xFF02) & 0x80, 0x80);
+        assert_eq!(cpu.interrupt_flags & SERIAL_IF, 0);
+
+        stub.reply.set(Some(0x99));
+        t_cycles(&mut cpu, SHIFT_CYCLES);
+        assert_eq!(Serial::read(&cpu, 0xFF01), 0x99);
+        assert_eq!(Serial::read(&cpu, 0xFF02) & 0x80, 0);
+        assert_eq!(cpu.interrupt_flags & SERIAL_IF, SERIAL_IF);
+    }
+}
@@ -1,17 +1,15 @@
 use crate::modules::cpu::Cpu;
 
-use super::bus::Bus;
-
 pub struct Stack {}
 
 impl Stack {
     pub fn push(cpu: &mut Cpu, data: u8) {
         cpu.registers.sp = cpu.registers.sp.wrapping_sub(1);
-        Bus::write(cpu, cpu.registers.sp, data);
+        cpu.write_cycle(cpu.registers.sp, data);
     }
 
     pub fn pop(cpu: &mut Cpu) -> u8 {
-        let val = Bus::read(cpu, cpu.registers.sp);
+        let val = cpu.read_cycle(cpu.registers.sp);
         cpu.registers.sp = cpu.registers.sp.wrapping_add(1);
         val
     }
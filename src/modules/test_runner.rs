@@ -0,0 +1,232 @@
+use std::path::Path;
+
+use super::{
+    bus::Bus, cpu::Cpu, dbg::Dbg, emu::Emu, options::Options, pacer::CPU_FREQUENCY,
+    screenshot::Screenshot,
+};
+
+// LD B,B, which Mooneye tests and dmg-acid2 execute once they're done
+pub const MOONEYE_BREAKPOINT: u8 = 0x40;
+// B, C, D, E, H, L after a passing Mooneye test
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+// Blargg's memory output: A001-A003 hold this signature, A000 reads 0x80
+// while the test runs and its result code after, 0 for a pass. The text
+// it printed follows from A004, null terminated.
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const BLARGG_RUNNING: u8 = 0x80;
+const BLARGG_TEXT: u16 = 0xA004;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum TestResult {
+    Passed,
+    Failed,
+    // No verdict within the time limit
+    Timeout,
+    // The CPU hit an instruction it can't execute
+    Crashed,
+}
+
+// Runs test ROMs headless and reads their verdict. Blargg's print "Passed"
+// or "Failed" over serial, or leave a result code in cartridge RAM.
+// Mooneye's load a Fibonacci sequence into the registers (0x42 in all of
+// them on failure) and execute LD B,B. ROMs that only show their verdict
+// on screen pass once it matches a reference image next to the ROM
+// (roms/halt_bug.gb -> roms/halt_bug.png).
+pub struct TestRunner {}
+
+impl TestRunner {
+    // Every ROM on the command line, true if all of them passed
+    pub fn run_all(options: &Options) -> bool {
+        let mut results = Vec::new();
+
+        for rom_path in &options.roms {
+            let mut cpu = Emu::boot(options, rom_path);
+            let reference = Self::reference(rom_path);
+            let (result, seconds) = Self::run(&mut cpu, options.timeout, reference.as_ref());
+
+            println!("{:?} after {:.1} s: {}", result, seconds, rom_path);
+            let message = match Self::blargg_status(&cpu) {
+                Some(_) => Self::blargg_text(&cpu),
+                None => cpu.dbg.message().to_string(),
+            };
+            if result != TestResult::Passed && !message.is_empty() {
+                println!("{}", message.trim_end());
+            }
+
+            results.push((rom_path, result));
+        }
+
+        println!();
+        for (rom_path, result) in &results {
+            println!("{:<8} {}", format!("{:?}", result), rom_path);
+        }
+
+        let passed = results
+            .iter()
+            .filter(|(_, result)| *result == TestResult::Passed)
+            .count();
+        println!("{} of {} passed", passed, results.len());
+
+        passed == results.len()
+    }
+
+    // Scale and pixels of the ROM's reference image, if it has one
+    pub fn reference(rom_path: &str) -> Option<(u32, Vec<u8>)> {
+        let path = Path::new(rom_path).with_extension("png");
+        if !path.exists() {
+            return None;
+        }
+
+        match Screenshot::load_reference(&path.to_string_lossy()) {
+            Ok(reference) => Some(reference),
+            Err(err) => {
+                println!("Ignoring reference image {}", err);
+                None
+            }
+        }
+    }
+
+    // Result and the emulated time it took, timeout is in emulated seconds
+    pub fn run(
+        cpu: &mut Cpu,
+        timeout: u32,
+        reference: Option<&(u32, Vec<u8>)>,
+    ) -> (TestResult, f64) {
+        let limit = timeout as u64 * CPU_FREQUENCY;
+        let mut frame = cpu.bus.ppu.current_frame;
+        // Cartridge RAM may still hold the result of an earlier run, it only
+        // counts once this run marked the test as running
+        let mut blargg_running = false;
+
+        let result = loop {
+            if cpu.timer.ticks >= limit {
+                break TestResult::Timeout;
+            }
+
+            let breakpoint = !cpu.halted
+                && !cpu.stopped
+                && Bus::read(cpu, cpu.registers.pc) == MOONEYE_BREAKPOINT;
+
+            if !cpu.step() {
+                break TestResult::Crashed;
+            }
+
+            if breakpoint {
+                let r = &cpu.registers;
+                let registers = [r.b, r.c, r.d, r.e, r.h, r.l];
+
+                if registers == MOONEYE_PASS {
+                    break TestResult::Passed;
+                }
+
+                if registers == MOONEYE_FAIL {
+                    break TestResult::Failed;
+                }
+            }
+
+            // Checked once per frame, and when a ROM like dmg-acid2 says it's
+            // done with LD B,B
+            if let Some((scale, pixels)) = reference {
+                if breakpoint || cpu.bus.ppu.current_frame != frame {
+                    frame = cpu.bus.ppu.current_frame;
+
+                    if Screenshot::pixels(cpu, *scale) == *pixels {
+                        break TestResult::Passed;
+                    }
+
+                    if breakpoint {
+                        break TestResult::Failed;
+                    }
+                }
+            }
+
+            match Self::blargg_status(cpu) {
+                Some(BLARGG_RUNNING) => blargg_running = true,
+                Some(0) if blargg_running => break TestResult::Passed,
+                Some(_) if blargg_running => break TestResult::Failed,
+                _ => (),
+            }
+
+            // Only the end of the message can hold a verdict that is new
+            if Dbg::update(cpu) {
+                let message = cpu.dbg.message();
+
+                if message.ends_with("Passed") {
+                    break TestResult::Passed;
+                }
+
+                if message.ends_with("Failed") {
+                    break TestResult::Failed;
+                }
+            }
+        };
+
+        (result, cpu.timer.ticks as f64 / CPU_FREQUENCY as f64)
+    }
+
+    // Result code at A000, None until the signature is there
+    fn blargg_status(cpu: &Cpu) -> Option<u8> {
+        let signature = [0xA001, 0xA002, 0xA003].map(|address| Bus::read(cpu, address));
+
+        (signature == BLARGG_SIGNATURE).then(|| Bus::read(cpu, 0xA000))
+    }
+
+    fn blargg_text(cpu: &Cpu) -> String {
+        (BLARGG_TEXT..=0xBFFF)
+            .map(|address| Bus::read(cpu, address))
+            .take_while(|&byte| byte != 0)
+            .map(|byte| byte as char)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TestResult, TestRunner};
+    use crate::modules::{emu::Emu, options::Options};
+
+    // Test ROMs in roms/ that pass today, with the emulated seconds each
+    // gets. Tetris_World and dmr aren't tests, interrupt_time is CGB only.
+    const SUITE: [(&str, u32); 16] = [
+        ("01-special.gb", 5),
+        ("02-interrupts.gb", 5),
+        ("03-op sp,hl.gb", 5),
+        ("04-op r,imm.gb", 5),
+        ("05-op rp.gb", 10),
+        ("06-ld r,r.gb", 5),
+        ("07-jr,jp,call,ret,rst.gb", 5),
+        ("08-misc instrs.gb", 5),
+        ("09-op r,r.gb", 15),
+        ("10-bit ops.gb", 20),
+        ("11-op a,(hl).gb", 25),
+        ("cpu_instrs.gb", 60),
+        ("instr_timing.gb", 5),
+        ("mem_timing.gb", 5),
+        ("halt_bug.gb", 5),
+        ("dmg-acid2.gb", 5),
+    ];
+
+    // Takes minutes in a debug build, run it with
+    // cargo test --release -- --ignored
+    #[test]
+    #[ignore]
+    fn roms_pass() {
+        let mut failures = Vec::new();
+
+        for (rom, timeout) in SUITE {
+            let path = format!("{}/roms/{}", env!("CARGO_MANIFEST_DIR"), rom);
+            let options = Options::parse(&[String::from("gameboy_emulator"), path.clone()]);
+            let mut cpu = Emu::boot(&options, &path);
+            let reference = TestRunner::reference(&path);
+            let (result, _) = TestRunner::run(&mut cpu, timeout, reference.as_ref());
+
+            if result != TestResult::Passed {
+                failures.push(format!("{}: {:?}", rom, result));
+            }
+        }
+
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
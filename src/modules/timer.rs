@@ -4,6 +4,8 @@ use crate::modules::cpu::Cpu;
 
 use super::dma::Dma;
 use super::ppu::Ppu;
+use super::savestate::{StateReader, StateWriter};
+use super::serial::Serial;
 
 #[derive(Default)]
 pub struct Timer {
@@ -12,39 +14,34 @@ pub struct Timer {
     tma: u8,
     tac: u8,
     pub ticks: u64,
+
+    // TAC-selected DIV bit ANDed with the timer enable bit, TIMA is clocked
+    // by the falling edge of this signal.
+    signal: bool,
+    // T-cycles left before TMA gets reloaded into TIMA after an overflow.
+    overflow_delay: u8,
+    // T-cycles left of the M-cycle in which TIMA was reloaded from TMA.
+    reload_cycle: u8,
 }
 
 impl Timer {
     pub fn tick(cpu: &mut Cpu) {
-        let prev_div = cpu.timer.div;
-        cpu.timer.div = cpu.timer.div.wrapping_add(1);
-
-        let mut timer_update: bool = false;
-
-        match cpu.timer.tac & 0b11 {
-            0b00 => {
-                timer_update = (prev_div & (1 << 9)) != 0 && cpu.timer.div & (1 << 9) == 0;
-            }
-            0b01 => {
-                timer_update = (prev_div & (1 << 3)) != 0 && cpu.timer.div & (1 << 3) == 0;
-            }
-            0b10 => {
-                timer_update = (prev_div & (1 << 5)) != 0 && cpu.timer.div & (1 << 5) == 0;
-            }
-            0b11 => {
-                timer_update = (prev_div & (1 << 7)) != 0 && cpu.timer.div & (1 << 7) == 0;
-            }
-            _ => {}
+        if cpu.timer.reload_cycle > 0 {
+            cpu.timer.reload_cycle -= 1;
         }
 
-        if timer_update && (cpu.timer.tac & (1 << 2)) != 0 {
-            if cpu.timer.tima == 0xFF {
+        if cpu.timer.overflow_delay > 0 {
+            cpu.timer.overflow_delay -= 1;
+
+            if cpu.timer.overflow_delay == 0 {
                 cpu.timer.tima = cpu.timer.tma;
+                cpu.timer.reload_cycle = 4;
                 interrupt::request(cpu, InterruptType::Timer);
             }
-
-            cpu.timer.tima += 1;
         }
+
+        cpu.timer.div = cpu.timer.div.wrapping_add(1);
+        Self::update_signal(cpu);
     }
 
     pub fn cycles(cpu: &mut Cpu, cycles: u64) {
@@ -52,6 +49,7 @@ impl Timer {
             for _ in 0..4 {
                 cpu.timer.ticks += 1;
                 Self::tick(cpu);
+                Serial::tick(cpu);
                 Ppu::tick(cpu);
             }
 
@@ -59,37 +57,261 @@ impl Timer {
         }
     }
 
+    fn selected_bit(&self) -> u16 {
+        match self.tac & 0b11 {
+            0b00 => 1 << 9,
+            0b01 => 1 << 3,
+            0b10 => 1 << 5,
+            _ => 1 << 7,
+        }
+    }
+
+    // Recomputes the timer signal and clocks TIMA on a falling edge. Called
+    // after every change to DIV or TAC, which is what makes DIV/TAC writes
+    // able to increment TIMA.
+    fn update_signal(cpu: &mut Cpu) {
+        let signal =
+            (cpu.timer.tac & (1 << 2)) != 0 && cpu.timer.div & cpu.timer.selected_bit() != 0;
+
+        if cpu.timer.signal && !signal {
+            Self::increment_tima(cpu);
+        }
+
+        cpu.timer.signal = signal;
+    }
+
+    fn increment_tima(cpu: &mut Cpu) {
+        let (tima, overflow) = cpu.timer.tima.overflowing_add(1);
+        cpu.timer.tima = tima;
+
+        if overflow {
+            // TIMA reads 0x00 for one M-cycle before TMA is loaded and the
+            // interrupt is requested.
+            cpu.timer.overflow_delay = 4;
+        }
+    }
+
     pub fn write(cpu: &mut Cpu, address: u16, value: u8) {
-        // panic!("Writing to timer: {:X} = {:X}", address, value);
         match address {
             0xFF04 => {
                 // DIV
                 cpu.timer.div = 0;
+                Self::update_signal(cpu);
             }
-            0xFF05 => {
-                // TIMA
+            // TIMA, a write while the reload is pending cancels it, a write
+            // in the reload cycle itself is overridden by TMA.
+            0xFF05 if cpu.timer.reload_cycle == 0 => {
                 cpu.timer.tima = value;
+                cpu.timer.overflow_delay = 0;
             }
             0xFF06 => {
                 // TMA
                 cpu.timer.tma = value;
+
+                if cpu.timer.reload_cycle > 0 {
+                    cpu.timer.tima = value;
+                }
             }
             0xFF07 => {
                 // TAC
-                cpu.timer.tac = value;
+                cpu.timer.tac = value & 0b111;
+                Self::update_signal(cpu);
             }
             _ => {}
         }
     }
 
     pub fn read(cpu: &Cpu, address: u16) -> u8 {
-        // panic!("Reading from timer: {:X}", address);
         match address {
             0xFF04 => (cpu.timer.div >> 8) as u8,
             0xFF05 => cpu.timer.tima,
             0xFF06 => cpu.timer.tma,
-            0xFF07 => cpu.timer.tac,
+            0xFF07 => cpu.timer.tac | 0b1111_1000,
             _ => 0,
         }
     }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.u16(self.div);
+        state.bytes(&[self.tima, self.tma, self.tac]);
+        state.u64(self.ticks);
+        state.bool(self.signal);
+        state.u8(self.overflow_delay);
+        state.u8(self.reload_cycle);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.div = state.u16()?;
+        self.tima = state.u8()?;
+        self.tma = state.u8()?;
+        self.tac = state.u8()?;
+        self.ticks = state.u64()?;
+        self.signal = state.bool()?;
+        self.overflow_delay = state.u8()?;
+        self.reload_cycle = state.u8()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Timer;
+    use crate::modules::cpu::Cpu;
+
+    const TIMER_IF: u8 = 0x04;
+
+    fn m_cycles(cpu: &mut Cpu, cycles: u32) {
+        for _ in 0..cycles * 4 {
+            Timer::tick(cpu);
+        }
+    }
+
+    // Timer running at 262144 Hz (DIV bit 3), TIMA one increment away from
+    // overflowing, which happens after exactly 4 M-cycles.
+    fn about_to_overflow() -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.timer.div = 0;
+        Timer::write(&mut cpu, 0xFF07, 0b101);
+        Timer::write(&mut cpu, 0xFF06, 0x42);
+        Timer::write(&mut cpu, 0xFF05, 0xFF);
+        cpu
+    }
+
+    #[test]
+    fn tima_counts_falling_edges_of_selected_div_bit() {
+        let mut cpu = Cpu::new();
+        cpu.timer.div = 0;
+        Timer::write(&mut cpu, 0xFF07, 0b101);
+
+        m_cycles(&mut cpu, 3);
+        assert_eq!(Timer::read(&cpu, 0xFF05), 0);
+
+        m_cycles(&mut cpu, 1);
+        assert_eq!(Timer::read(&cpu, 0xFF05), 1);
+
+        m_cycles(&mut cpu, 40);
+        assert_eq!(Timer::read(&cpu, 0xFF05), 11);
+    }
+
+    #[test]
+    fn disabled_timer_does_not_count() {
+        let mut cpu = Cpu::new();
+        cpu.timer.div = 0;
+        Timer::write(&mut cpu, 0xFF07, 0b001);
+
+        m_cycles(&mut cpu, 100);
+        assert_eq!(Timer::read(&cpu, 0xFF05), 0);
+    }
+
+    #[test]
+    fn overflow_reloads_tma_one_m_cycle_later() {
+        let mut cpu = about_to_overflow();
+
+        m_cycles(&mut cpu, 4);
+        assert_eq!(Timer::read(&cpu, 0xFF05), 0x00);
+        assert_eq!(cpu.interrupt_flags & TIMER_IF, 0);
+
+        m_cycles(&mut cpu, 1);
+        assert_eq!(Timer::read(&cpu, 0xFF05), 0x42);
+        assert_eq!(cpu.interrupt_flags & TIMER_IF, TIMER_IF);
+
+        // The reload is not followed by an extra increment
+        m_cycles(&mut cpu, 2);
+        assert_eq!(Timer::read(&cpu, 0xFF05), 0x42);
+    }
+
+    #[test]
+    fn tima_write_during_overflow_delay_cancels_reload() {
+        let mut cpu = about_to_overflow();
+
+        m_cycles(&mut cpu, 4);
+        Timer::write(&mut cpu, 0xFF05, 0x10);
+        m_cycles(&mut cpu, 1);
+
+        assert_eq!(Timer::read(&cpu, 0xFF05), 0x10);
+        assert_eq!(cpu.interrupt_flags & TIMER_IF, 0);
+    }
+
+    #[test]
+    fn tima_write_in_reload_cycle_is_ignored() {
+        let mut cpu = about_to_overflow();
+
+        m_cycles(&mut cpu, 5);
+        Timer::write(&mut cpu, 0xFF05, 0x10);
+
+        assert_eq!(Timer::read(&cpu, 0xFF05), 0x42);
+    }
+
+    #[test]
+    fn tma_write_in_reload_cycle_reaches_tima() {
+        let mut cpu = about_to_overflow();
+
+        m_cycles(&mut cpu, 5);
+        Timer::write(&mut cpu, 0xFF06, 0x99);
+
+        assert_eq!(Timer::read(&cpu, 0xFF05), 0x99);
+
+        m_cycles(&mut cpu, 1);
+        Timer::write(&mut cpu, 0xFF06, 0x11);
+        assert_eq!(Timer::read(&cpu, 0xFF05), 0x99);
+    }
+
+    #[test]
+    fn div_write_with_selected_bit_set_increments_tima() {
+        let mut cpu = Cpu::new();
+        cpu.timer.div = 0;
+        Timer::write(&mut cpu, 0xFF07, 0b101);
+
+        m_cycles(&mut cpu, 2);
+        assert_eq!(Timer::read(&cpu, 0xFF05), 0);
+
+        Timer::write(&mut cpu, 0xFF04, 0);
+        assert_eq!(Timer::read(&cpu, 0xFF05), 1);
+        assert_eq!(Timer::read(&cpu, 0xFF04), 0);
+    }
+
+    #[test]
+    fn div_write_with_selected_bit_clear_does_not_increment() {
+        let mut cpu = Cpu::new();
+        cpu.timer.div = 0;
+        Timer::write(&mut cpu, 0xFF07, 0b101);
+
+        m_cycles(&mut cpu, 1);
+        Timer::write(&mut cpu, 0xFF04, 0);
+        assert_eq!(Timer::read(&cpu, 0xFF05), 0);
+    }
+
+    #[test]
+    fn tac_disable_with_selected_bit_set_increments_tima() {
+        let mut cpu = Cpu::new();
+        cpu.timer.div = 0;
+        Timer::write(&mut cpu, 0xFF07, 0b101);
+
+        m_cycles(&mut cpu, 2);
+        Timer::write(&mut cpu, 0xFF07, 0b001);
+        assert_eq!(Timer::read(&cpu, 0xFF05), 1);
+    }
+
+    #[test]
+    fn tac_frequency_change_to_cleared_bit_increments_tima() {
+        let mut cpu = Cpu::new();
+        cpu.timer.div = 0;
+        Timer::write(&mut cpu, 0xFF07, 0b101);
+
+        // DIV bit 3 set, bit 9 clear
+        m_cycles(&mut cpu, 2);
+        Timer::write(&mut cpu, 0xFF07, 0b100);
+        assert_eq!(Timer::read(&cpu, 0xFF05), 1);
+    }
+
+    #[test]
+    fn tac_reads_unused_bits_as_one() {
+        let mut cpu = Cpu::new();
+        Timer::write(&mut cpu, 0xFF07, 0xFD);
+        assert_eq!(Timer::read(&cpu, 0xFF07), 0xFD);
+
+        Timer::write(&mut cpu, 0xFF07, 0x00);
+        assert_eq!(Timer::read(&cpu, 0xFF07), 0xF8);
+    }
 }
@@ -0,0 +1,169 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use super::{bus::Bus, cpu::Cpu, disassembler::Disassembler};
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TraceFormat {
+    // One line per instruction as Gameboy Doctor expects them:
+    // A:00 F:11 B:22 C:33 D:44 E:55 H:66 L:77 SP:8888 PC:9999 PCMEM:AA,BB,CC,DD
+    Doctor,
+    // Instruction count, cycles, bank:address, bytes, disassembly and registers
+    Verbose,
+}
+
+pub struct TraceOptions {
+    pub path: Option<String>,
+    pub format: TraceFormat,
+    // Only instructions at these inclusive PC ranges, any PC if empty
+    pub ranges: Vec<(u16, u16)>,
+    // Only instructions in these ROM banks, any code if empty
+    pub banks: Vec<usize>,
+    // Instructions are counted from power on, [start, stop) is traced
+    pub start: u64,
+    pub stop: Option<u64>,
+}
+
+impl TraceOptions {
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            format: TraceFormat::Doctor,
+            ranges: Vec::new(),
+            banks: Vec::new(),
+            start: 0,
+            stop: None,
+        }
+    }
+}
+
+// Writes the CPU state before every executed instruction that passes the
+// filters. Output is buffered, it is flushed when the tracer is dropped.
+pub struct Tracer {
+    out: BufWriter<File>,
+    format: TraceFormat,
+    ranges: Vec<(u16, u16)>,
+    banks: Vec<usize>,
+    start: u64,
+    stop: Option<u64>,
+    // Instructions executed so far
+    count: u64,
+}
+
+impl Tracer {
+    // None if tracing isn't enabled
+    pub fn new(options: &TraceOptions) -> Result<Option<Self>, String> {
+        let path = match &options.path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let file = File::create(path).map_err(|err| format!("{}: {}", path, err))?;
+
+        Ok(Some(Self {
+            out: BufWriter::new(file),
+            format: options.format,
+            ranges: options.ranges.clone(),
+            banks: options.banks.clone(),
+            start: options.start,
+            stop: options.stop,
+            count: 0,
+        }))
+    }
+
+    // Called before the instruction at PC executes
+    pub fn instruction(cpu: &mut Cpu) {
+        let mut tracer = match cpu.tracer.take() {
+            Some(tracer) => tracer,
+            None => return,
+        };
+
+        let count = tracer.count;
+        tracer.count += 1;
+
+        if tracer.stop.is_some_and(|stop| count >= stop) {
+            // Nothing more to write, dropping the tracer flushes it
+            return;
+        }
+
+        if count >= tracer.start && tracer.matches(cpu) {
+            let line = match tracer.format {
+                TraceFormat::Doctor => Self::doctor(cpu),
+                TraceFormat::Verbose => Self::verbose(cpu, count),
+            };
+
+            if let Err(err) = writeln!(tracer.out, "{}", line) {
+                eprintln!("Trace stopped: {}", err);
+                return;
+            }
+        }
+
+        cpu.tracer = Some(tracer);
+    }
+
+    fn matches(&self, cpu: &Cpu) -> bool {
+        let pc = cpu.registers.pc;
+
+        let in_range = self.ranges.is_empty()
+            || self
+                .ranges
+                .iter()
+                .any(|&(start, end)| (start..=end).contains(&pc));
+
+        // Code running from RAM isn't in any ROM bank
+        let in_bank =
+            self.banks.is_empty() || pc < 0x8000 && self.banks.contains(&cpu.bus.cart.rom_bank(pc));
+
+        in_range && in_bank
+    }
+
+    fn doctor(cpu: &Cpu) -> String {
+        let r = &cpu.registers;
+        let pcmem = |offset: u16| Bus::read(cpu, r.pc.wrapping_add(offset));
+
+        format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp, r.pc,
+            pcmem(0), pcmem(1), pcmem(2), pcmem(3)
+        )
+    }
+
+    fn verbose(cpu: &Cpu, count: u64) -> String {
+        let r = &cpu.registers;
+        let (text, length) = Disassembler::instruction(&|address| Bus::read(cpu, address), r.pc);
+        let bytes: Vec<String> = (0..length)
+            .map(|i| format!("{:02X}", Bus::read(cpu, r.pc.wrapping_add(i))))
+            .collect();
+        let bank = if r.pc < 0x8000 {
+            cpu.bus.cart.rom_bank(r.pc)
+        } else {
+            0
+        };
+        let flag = |set: bool, name: char| if set { name } else { '-' };
+
+        format!(
+            "{:>10} {:>12} {:02X}:{:04X}  {:<8}  {:<20} A:{:02X} F:{}{}{}{} BC:{:02X}{:02X} DE:{:02X}{:02X} HL:{:02X}{:02X} SP:{:04X} IME:{} LY:{:02X}",
+            count,
+            cpu.timer.ticks,
+            bank,
+            r.pc,
+            bytes.join(" "),
+            text,
+            r.a,
+            flag(r.flag_z(), 'Z'),
+            flag(r.flag_n(), 'N'),
+            flag(r.flag_h(), 'H'),
+            flag(r.flag_c(), 'C'),
+            r.b,
+            r.c,
+            r.d,
+            r.e,
+            r.h,
+            r.l,
+            r.sp,
+            cpu.int_master_enabled as u8,
+            cpu.bus.lcd.ly
+        )
+    }
+}
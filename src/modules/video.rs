@@ -0,0 +1,279 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+};
+
+use super::{
+    cpu::Cpu,
+    pacer::{CHANNELS, CPU_FREQUENCY, CYCLES_PER_FRAME, SAMPLE_RATE},
+    screenshot::Screenshot,
+};
+
+const XRES: u32 = 160;
+const YRES: u32 = 144;
+const FRAME_BYTES: u32 = XRES * YRES * 3;
+// 16-bit samples for every channel
+const BLOCK_ALIGN: u32 = CHANNELS as u32 * 2;
+
+// Index entry flag for frames that decode on their own, which all do here
+const AVIIF_KEYFRAME: u32 = 0x10;
+const AVIF_HASINDEX: u32 = 0x10;
+// Players don't agree on anything past 2 GB in a plain AVI, about 8 minutes
+const MAX_MOVI_SIZE: u32 = 2_000_000_000;
+
+// Records every emulated frame into an uncompressed AVI, with a PCM audio
+// track so players keep it at the Game Boy's frame rate however fast the
+// host ran. There is no APU yet, so the audio is silence.
+pub struct VideoRecorder {
+    out: BufWriter<File>,
+    frames: u32,
+    samples: u64,
+    // Fractional samples carried over to the next frame, in CPU cycles
+    sample_remainder: u64,
+    // Chunk id, offset from the movi list type and size of every chunk
+    index: Vec<(&'static [u8; 4], u32, u32)>,
+    movi_size: u32,
+}
+
+impl VideoRecorder {
+    pub fn new(path: &str) -> Result<Self, String> {
+        let file = File::create(path).map_err(|err| format!("{}: {}", path, err))?;
+
+        let mut recorder = Self {
+            out: BufWriter::new(file),
+            frames: 0,
+            samples: 0,
+            sample_remainder: 0,
+            index: Vec::new(),
+            movi_size: 4,
+        };
+
+        // Rewritten with the real lengths by finish()
+        let header = recorder.header();
+        recorder.write(&header)?;
+
+        Ok(recorder)
+    }
+
+    // Called once per emulated frame, with the screen as it was left
+    pub fn frame(&mut self, cpu: &Cpu) -> Result<(), String> {
+        if self.movi_size > MAX_MOVI_SIZE {
+            return Err(String::from("reached the 2 GB AVI size limit"));
+        }
+
+        // Bottom-up BGR rows, as a DIB stores them
+        let pixels = Screenshot::pixels(cpu, 1);
+        let mut frame = Vec::with_capacity(FRAME_BYTES as usize);
+        for row in pixels.chunks((XRES * 3) as usize).rev() {
+            for pixel in row.chunks(3) {
+                frame.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            }
+        }
+        self.chunk(b"00db", &frame)?;
+
+        let cycles = self.sample_remainder + SAMPLE_RATE as u64 * CYCLES_PER_FRAME;
+        let samples = cycles / CPU_FREQUENCY;
+        self.sample_remainder = cycles % CPU_FREQUENCY;
+        self.chunk(b"01wb", &vec![0; (samples * BLOCK_ALIGN as u64) as usize])?;
+
+        self.frames += 1;
+        self.samples += samples;
+
+        Ok(())
+    }
+
+    // Writes the index and the final header, returns the number of frames
+    pub fn finish(mut self) -> Result<u32, String> {
+        let mut index = Vec::with_capacity(self.index.len() * 16);
+        for (id, offset, size) in &self.index {
+            index.extend_from_slice(*id);
+            index.extend_from_slice(&AVIIF_KEYFRAME.to_le_bytes());
+            index.extend_from_slice(&offset.to_le_bytes());
+            index.extend_from_slice(&size.to_le_bytes());
+        }
+        self.write(&Self::chunk_bytes(b"idx1", &index))?;
+
+        let header = self.header();
+        self.out
+            .seek(SeekFrom::Start(0))
+            .map_err(|err| err.to_string())?;
+        self.write(&header)?;
+        self.out.flush().map_err(|err| err.to_string())?;
+
+        Ok(self.frames)
+    }
+
+    fn chunk(&mut self, id: &'static [u8; 4], data: &[u8]) -> Result<(), String> {
+        self.index.push((id, self.movi_size, data.len() as u32));
+        self.movi_size += 8 + data.len() as u32;
+
+        self.write(&Self::chunk_bytes(id, data))
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.out.write_all(bytes).map_err(|err| err.to_string())
+    }
+
+    // Everything up to the first chunk in the movi list
+    fn header(&self) -> Vec<u8> {
+        let dword = |value: u32| value.to_le_bytes();
+        let byte_rate = SAMPLE_RATE as u32 * BLOCK_ALIGN;
+
+        let main = [
+            // Microseconds per frame
+            dword((CYCLES_PER_FRAME * 1_000_000 / CPU_FREQUENCY) as u32),
+            dword(FRAME_BYTES * 60 + byte_rate),
+            dword(0),
+            dword(AVIF_HASINDEX),
+            dword(self.frames),
+            dword(0),
+            // Streams
+            dword(2),
+            dword(FRAME_BYTES),
+            dword(XRES),
+            dword(YRES),
+            dword(0),
+            dword(0),
+            dword(0),
+            dword(0),
+        ]
+        .concat();
+
+        let video = Self::list(
+            b"strl",
+            &[
+                Self::stream_header(
+                    b"vids",
+                    b"DIB ",
+                    // Frames per second as CPU_FREQUENCY / CYCLES_PER_FRAME
+                    CYCLES_PER_FRAME as u32,
+                    CPU_FREQUENCY as u32,
+                    self.frames,
+                    FRAME_BYTES,
+                    0,
+                ),
+                Self::chunk_bytes(
+                    b"strf",
+                    &[
+                        // BITMAPINFOHEADER, a positive height is bottom-up
+                        dword(40),
+                        dword(XRES),
+                        dword(YRES),
+                        [1, 0, 24, 0],
+                        dword(0),
+                        dword(FRAME_BYTES),
+                        dword(0),
+                        dword(0),
+                        dword(0),
+                        dword(0),
+                    ]
+                    .concat(),
+                ),
+            ]
+            .concat(),
+        );
+
+        let audio = Self::list(
+            b"strl",
+            &[
+                Self::stream_header(
+                    b"auds",
+                    &[0; 4],
+                    BLOCK_ALIGN,
+                    byte_rate,
+                    self.samples as u32,
+                    byte_rate / 10,
+                    BLOCK_ALIGN,
+                ),
+                Self::chunk_bytes(
+                    b"strf",
+                    &[
+                        // PCMWAVEFORMAT
+                        [1, 0, CHANNELS, 0],
+                        dword(SAMPLE_RATE as u32),
+                        dword(byte_rate),
+                        [BLOCK_ALIGN as u8, 0, 16, 0],
+                    ]
+                    .concat(),
+                ),
+            ]
+            .concat(),
+        );
+
+        let header = Self::list(
+            b"hdrl",
+            &[Self::chunk_bytes(b"avih", &main), video, audio].concat(),
+        );
+
+        // Sizes of the whole file and the movi list, which the chunks written
+        // so far and the index follow
+        let index_size = 8 + self.index.len() as u32 * 16;
+        let riff_size = 4 + header.len() as u32 + 8 + self.movi_size + index_size;
+
+        [
+            b"RIFF".to_vec(),
+            dword(riff_size).to_vec(),
+            b"AVI ".to_vec(),
+            header,
+            b"LIST".to_vec(),
+            dword(self.movi_size).to_vec(),
+            b"movi".to_vec(),
+        ]
+        .concat()
+    }
+
+    // AVISTREAMHEADER, rate / scale is the number of units per second and a
+    // sample size of 0 means every chunk is one unit
+    fn stream_header(
+        kind: &[u8; 4],
+        handler: &[u8; 4],
+        scale: u32,
+        rate: u32,
+        length: u32,
+        buffer_size: u32,
+        sample_size: u32,
+    ) -> Vec<u8> {
+        let dword = |value: u32| value.to_le_bytes();
+
+        let header = [
+            *kind,
+            *handler,
+            dword(0),
+            // Priority and language
+            dword(0),
+            dword(0),
+            dword(scale),
+            dword(rate),
+            dword(0),
+            dword(length),
+            dword(buffer_size),
+            // Default quality
+            dword(u32::MAX),
+            dword(sample_size),
+            // Frame rectangle
+            [0, 0, 0, 0],
+            [XRES as u8, 0, YRES as u8, 0],
+        ]
+        .concat();
+
+        Self::chunk_bytes(b"strh", &header)
+    }
+
+    fn list(kind: &[u8; 4], children: &[u8]) -> Vec<u8> {
+        Self::chunk_bytes(b"LIST", &[kind.as_slice(), children].concat())
+    }
+
+    fn chunk_bytes(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = Vec::with_capacity(8 + data.len() + 1);
+        chunk.extend_from_slice(id);
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+
+        // Chunks are word aligned
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+
+        chunk
+    }
+}